@@ -1,39 +1,186 @@
 use std::{
+    collections::HashMap,
     fs::{self, File},
     io::BufReader,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
 use log::warn;
 
 use crate::{
-    cddb_read::read_cddb, date::Date, err::Result, track_info::TrackInfo,
+    cddb_read::read_cddb,
+    cdindex::CdIndex,
+    cdtext::{CdText, CdTextKind},
+    config::Config,
+    cover::Cover,
+    cue::{Cue, FRAMES_PER_SECOND},
+    date::Date,
+    err::Result,
+    flac_meta::FlacMeta,
+    get_perf::get_perf,
+    image::Image,
+    lyrics::Lyrics,
+    sort,
+    tag_map::TagMap,
+    tags,
+    track_info::TrackInfo,
+    wav::PcmFormat,
 };
 
+/// Number of samples per channel in single CD sector.
+const SECTOR_SAMPLES: u64 = 588;
+
 #[derive(Default, Debug)]
 pub struct AlbumInfo {
     pub cdindex: Option<String>,
     pub cddb: Option<u32>,
     pub artist: Option<String>,
+    pub artist_sort: Option<String>,
+    /// The album artist was taken from the artist of the first track.
+    pub artist_auto: bool,
     pub disc_name: Option<String>,
     pub album_title: Option<String>,
     pub disc: Option<usize>,
+    /// Number of discs in the set.
+    pub disc_total: Option<usize>,
     pub date: Option<Date>,
     pub genre: Option<String>,
+    pub cover: Option<Arc<Cover>>,
+    /// Barcode (UPC/EAN). It is stored on the disc as media catalog
+    /// number.
+    pub barcode: Option<String>,
+    pub label: Option<String>,
+    pub catalog: Option<String>,
+    /// Album with tracks by various artists.
+    pub compilation: bool,
+    /// Audio image that must be split into the tracks before encoding.
+    pub image: Option<Image>,
 
     pub tracks: Vec<(TrackInfo, PathBuf)>,
 }
 
 impl AlbumInfo {
-    pub fn from_dir<P>(path: P) -> Result<Self>
+    /// Loads album from the given directory. The directory contains wav
+    /// files with `.inf` files, or image with cue sheet. `path` may
+    /// also be path to the cue sheet. If `big_endian` is set, raw images of
+    /// type `BINARY` are read as big-endian.
+    pub fn from_dir<P>(path: P, big_endian: bool) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let mut res = Self::default();
+        if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("cue"))
+        {
+            res.load_cue(path, big_endian)?;
+        } else if let Some(cue) = find_cue(path)? {
+            res.load_cue(&cue, big_endian)?;
+        } else {
+            res.load_dir(path)?;
+        }
+        Ok(res)
+    }
+
+    /// Loads album from already encoded flac files in the given directory.
+    pub fn from_flac_dir<P>(path: P, map: &TagMap) -> Result<Self>
     where
         P: AsRef<Path>,
     {
         let mut res = Self::default();
-        res.load_dir(path.as_ref())?;
+        for f in fs::read_dir(path)? {
+            let path = f?.path();
+            if path.extension().is_none_or(|e| e != "flac") {
+                continue;
+            }
+
+            let comments = FlacMeta::read(&path)
+                .and_then(|m| m.comments().transpose())
+                .map(Option::unwrap_or_default);
+            match comments {
+                Ok(c) => res
+                    .tracks
+                    .push((tags::from_vorbis_comments(&c, map), path)),
+                Err(e) => warn!("Failed to read flac file {path:?}: {e}"),
+            }
+        }
+
+        res.tracks.sort_by(|(a, ap), (b, bp)| {
+            a.track.cmp(&b.track).then(ap.cmp(bp))
+        });
+        res.fill_from_tracks();
         Ok(res)
     }
 
+    /// Sets the front cover of the album and all its tracks.
+    pub fn set_cover(&mut self, cover: Cover) {
+        let cover = Arc::new(cover);
+        for (t, _) in &mut self.tracks {
+            t.cover = Some(cover.clone());
+        }
+        self.cover = Some(cover);
+    }
+
+    /// Fills the album info that depend on the config. This sets the album
+    /// artist of compilations and generates the missing sort names.
+    pub fn fill_from_config(&mut self, config: &Config) {
+        // Artist that wasn't explicitly given is replaced, so that all
+        // compilations have the same album artist.
+        let replace =
+            self.artist_auto || self.artist.as_deref().is_none_or(is_various);
+        let artist = config.various_artists();
+        if self.compilation
+            && replace
+            && self.artist.as_deref() != Some(artist)
+        {
+            for (t, _) in &mut self.tracks {
+                if t.album_artist.is_none() || t.album_artist == self.artist {
+                    t.album_artist = Some(artist.to_owned());
+                    t.album_artist_sort = None;
+                }
+            }
+            self.artist = Some(artist.to_owned());
+            self.artist_sort = None;
+            self.artist_auto = false;
+        }
+        self.fill_sort_names(&config.sort_names);
+    }
+
+    /// Generates the missing sort names of the album artist and of the
+    /// track artists.
+    fn fill_sort_names(&mut self, overrides: &HashMap<String, String>) {
+        self.artist_sort = self.artist_sort.take().or_else(|| {
+            self.artist.as_ref().map(|a| sort::sort_name(a, overrides))
+        });
+        for (t, _) in &mut self.tracks {
+            t.album_artist_sort = t.album_artist_sort.take().or_else(|| {
+                t.album_artist.as_ref().map(|a| {
+                    if t.album_artist == self.artist
+                        && let Some(s) = &self.artist_sort
+                    {
+                        s.clone()
+                    } else {
+                        sort::sort_name(a, overrides)
+                    }
+                })
+            });
+            t.artist_sort = t.artist_sort.take().or_else(|| {
+                t.artist.as_ref().map(|a| sort::sort_name(a, overrides))
+            });
+        }
+    }
+
+    /// Splits the audio image into the wav files of the tracks. Does
+    /// nothing if the album is not loaded from image.
+    pub fn split_image(&self) -> Result<()> {
+        match &self.image {
+            Some(i) => i.split(),
+            None => Ok(()),
+        }
+    }
+
     pub fn normalize(&mut self) {
         for (t, _) in &mut self.tracks {
             t.normalize();
@@ -52,24 +199,166 @@ impl AlbumInfo {
                 continue;
             }
 
+            // cdparanoia doesn't create `.inf` files, but the track number
+            // is in the file name.
+            let paranoia = cdparanoia_track(&path);
+            if paranoia == Some(0) {
+                warn!("Skipping hidden track {path:?}.");
+                continue;
+            }
+
             path.set_extension("inf");
+            if paranoia.is_some() && !path.exists() {
+                let info = TrackInfo {
+                    track: paranoia,
+                    ..Default::default()
+                };
+                self.tracks.push((info, f.path()));
+                continue;
+            }
+
             match TrackInfo::from_file(path) {
                 Ok(i) => self.tracks.push((i, f.path())),
                 Err(e) => {
                     let path = f.path();
                     warn!("Failed to read info file for {path:?}: {e}");
-                    self.tracks.push((TrackInfo::default(), path));
+                    let info = TrackInfo {
+                        track: paranoia,
+                        ..Default::default()
+                    };
+                    self.tracks.push((info, path));
                 }
             }
         }
 
         self.tracks.sort_by_key(|t| t.0.track);
 
+        // CD-TEXT is usually more accurate than CDDB.
+        let cdtext_file = path.join("audio.cdtext");
+        if cdtext_file.exists()
+            && let Err(e) = self.read_cdtext(&cdtext_file)
+        {
+            warn!("Failed to read cdtext file {cdtext_file:?}: {e}");
+        }
+
         let cddb_file = path.join("audio.cddb");
         if let Err(e) = self.read_cddb(&cddb_file) {
             warn!("Failed to read cddb file {cddb_file:?}: {e}");
         }
 
+        let cdindex_file = path.join("audio.cdindex");
+        if cdindex_file.exists()
+            && let Err(e) = self.read_cdindex(&cdindex_file)
+        {
+            warn!("Failed to read cdindex file {cdindex_file:?}: {e}");
+        }
+
+        self.fill_from_tracks();
+        self.load_extras(path);
+        Ok(())
+    }
+
+    /// Loads album from cue sheet with audio image. The tracks are
+    /// split from the image at their index 1. Data tracks are skipped.
+    fn load_cue(&mut self, path: &Path, big_endian: bool) -> Result<()> {
+        let cue = Cue::from_file(path)?;
+        let image = Image::new(&cue, big_endian)?;
+        let is_cd = image.format == PcmFormat::CD;
+
+        self.cddb = cue.discid;
+        self.artist = cue.performer;
+        self.disc_name = cue.title;
+        self.date = cue.date;
+        self.genre = cue.genre;
+        self.barcode = cue.catalog;
+        self.compilation = self.artist.as_deref().is_some_and(is_various);
+        if self.compilation {
+            self.artist = None;
+        }
+
+        let rate = image.format.sample_rate as u64;
+        // Data tracks are not encoded, but they are part of the disc.
+        let toc = is_cd
+            .then(|| {
+                cdtoc(image.tracks.iter().map(|t| {
+                    let start = t.start / SECTOR_SAMPLES;
+                    Some((start as u32, (t.len / SECTOR_SAMPLES) as u32))
+                }))
+            })
+            .flatten();
+        let track_total = cue.tracks.len();
+        for (i, (t, it)) in
+            cue.tracks.into_iter().zip(&image.tracks).enumerate()
+        {
+            if it.data {
+                continue;
+            }
+            let feat = t
+                .title
+                .as_ref()
+                .and_then(|t| {
+                    get_perf(t)
+                        .inspect_err(|e| {
+                            warn!("Failed to parse features from '{t}': {e}")
+                        })
+                        .ok()
+                })
+                .unwrap_or_default();
+            let info = TrackInfo {
+                title: t.title,
+                artist: t.performer,
+                feat,
+                lyricist: t.songwriter.or_else(|| cue.songwriter.clone()),
+                isrc: t.isrc,
+                track: Some(t.number),
+                track_total: Some(track_total),
+                pre_emphasis: t.pre_emphasis,
+                start: is_cd.then_some((it.start / SECTOR_SAMPLES) as u32),
+                length: is_cd.then_some((it.len / SECTOR_SAMPLES) as u32),
+                indices: it
+                    .indices
+                    .iter()
+                    .map(|(n, p)| (*n, (p * FRAMES_PER_SECOND / rate) as u32))
+                    .collect(),
+                cdtoc: toc.clone(),
+                ..Default::default()
+            };
+            self.tracks.push((info, image.track_path(i)));
+        }
+        self.image = Some(image);
+
+        self.fill_from_tracks();
+        match path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => self.load_extras(p),
+            _ => self.load_extras(Path::new(".")),
+        }
+        Ok(())
+    }
+
+    /// Loads the lyrics and the cover from the given directory.
+    fn load_extras(&mut self, path: &Path) {
+        let lyrics = Lyrics::files(path);
+        for (t, p) in &mut self.tracks {
+            let Some(file) = Lyrics::find(&lyrics, p, t) else {
+                continue;
+            };
+            match Lyrics::from_file(file) {
+                Ok(l) => t.lyrics = Some(l),
+                Err(e) => warn!("Failed to load lyrics {file:?}: {e}"),
+            }
+        }
+
+        if let Some(cover) = Cover::find(path) {
+            match Cover::from_file(&cover) {
+                Ok(c) => self.set_cover(c),
+                Err(e) => warn!("Failed to load cover {cover:?}: {e}"),
+            }
+        }
+    }
+
+    /// Fills the missing album info from the tracks and the missing track
+    /// info from the album.
+    fn fill_from_tracks(&mut self) {
         self.cdindex = self.cdindex.take().or_else(|| {
             self.tracks
                 .iter()
@@ -79,11 +368,29 @@ impl AlbumInfo {
         self.cddb = self
             .cddb
             .or_else(|| self.tracks.iter().flat_map(|(t, _)| t.cddb).next());
+        self.compilation |= self.tracks.iter().any(|(t, _)| t.compilation);
         self.artist = self.artist.take().or_else(|| {
             self.tracks
                 .iter()
                 .flat_map(|(t, _)| t.album_artist.clone())
-                .chain(self.tracks.iter().flat_map(|(t, _)| t.artist.clone()))
+                .next()
+        });
+        if self.artist.is_none() && self.has_various_artists() {
+            self.compilation = true;
+        }
+        if !self.compilation && self.artist.is_none() {
+            self.artist = self
+                .tracks
+                .iter()
+                .flat_map(|(t, _)| t.artist.clone())
+                .next();
+            self.artist_auto = self.artist.is_some();
+        }
+        self.compilation |= self.artist.as_deref().is_some_and(is_various);
+        self.artist_sort = self.artist_sort.take().or_else(|| {
+            self.tracks
+                .iter()
+                .flat_map(|(t, _)| t.album_artist_sort.clone())
                 .next()
         });
         self.disc_name = self.disc_name.take().or_else(|| {
@@ -102,6 +409,9 @@ impl AlbumInfo {
         self.disc = self
             .disc
             .or_else(|| self.tracks.iter().flat_map(|(t, _)| t.disc).next());
+        self.disc_total = self.disc_total.or_else(|| {
+            self.tracks.iter().flat_map(|(t, _)| t.disc_total).next()
+        });
         self.date = self
             .date
             .or_else(|| self.tracks.iter().flat_map(|(t, _)| t.date).max());
@@ -109,39 +419,248 @@ impl AlbumInfo {
             self.tracks.iter().flat_map(|(t, _)| t.genre.clone()).next()
         });
 
+        self.barcode = self.barcode.take().or_else(|| {
+            self.tracks
+                .iter()
+                .flat_map(|(t, _)| t.barcode.clone())
+                .next()
+        });
+        self.label = self.label.take().or_else(|| {
+            self.tracks.iter().flat_map(|(t, _)| t.label.clone()).next()
+        });
+        self.catalog = self.catalog.take().or_else(|| {
+            self.tracks
+                .iter()
+                .flat_map(|(t, _)| t.catalog.clone())
+                .next()
+        });
+        let cdtoc =
+            cdtoc(self.tracks.iter().map(|(t, _)| t.start.zip(t.length)));
+
+        let track_total = self.tracks.len();
         for (t, _) in self.tracks.iter_mut() {
+            t.track_total = t.track_total.or(Some(track_total));
             t.cdindex = t.cdindex.take().or_else(|| self.cdindex.clone());
             t.cddb = t.cddb.or(self.cddb);
             t.album_artist =
                 t.album_artist.take().or_else(|| self.artist.clone());
+            t.album_artist_sort = t
+                .album_artist_sort
+                .take()
+                .or_else(|| self.artist_sort.clone());
             t.disc_name =
                 t.disc_name.take().or_else(|| self.disc_name.clone());
             t.album = t.album.take().or_else(|| self.album_title.clone());
             t.disc = t.disc.or(self.disc);
+            t.disc_total = t.disc_total.or(self.disc_total);
+            t.compilation = self.compilation;
             t.date = t.date.or(self.date);
             t.genre = t.genre.take().or_else(|| self.genre.clone());
             t.artist = t.artist.take().or_else(|| self.artist.clone());
+            t.barcode = t.barcode.take().or_else(|| self.barcode.clone());
+            t.label = t.label.take().or_else(|| self.label.clone());
+            t.catalog = t.catalog.take().or_else(|| self.catalog.clone());
+            t.cdtoc = t.cdtoc.take().or_else(|| cdtoc.clone());
         }
+    }
 
-        Ok(())
+    /// Checks whether the tracks are by different artists so that none of
+    /// them performs more than half of the tracks.
+    fn has_various_artists(&self) -> bool {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for a in self.tracks.iter().flat_map(|(t, _)| &t.artist) {
+            *counts.entry(a).or_default() += 1;
+        }
+        counts.len() > 1 && counts.values().all(|c| c * 2 <= self.tracks.len())
     }
 
     fn read_cddb(&mut self, cddb_file: &Path) -> Result<()> {
         let mut cddb = read_cddb(BufReader::new(File::open(cddb_file)?))?;
 
-        self.cddb = cddb
-            .remove("DISCID")
-            .map(|c| u32::from_str_radix(&c, 16))
-            .transpose()?;
-        if let Some(at) = cddb.remove("DTITLE") {
-            if let Some((artist, album)) = at.split_once(" / ") {
-                self.artist = Some(artist.to_owned());
-                self.disc_name = Some(album.to_owned());
+        // Invalid values are skipped, so that the other fields are not lost.
+        self.cddb = cddb.remove("DISCID").and_then(|c| {
+            u32::from_str_radix(c.trim(), 16)
+                .inspect_err(|e| warn!("Invalid DISCID '{c}' in cddb: {e}"))
+                .ok()
+        });
+        if let Some(at) = cddb.remove("DTITLE")
+            && let Some((artist, album)) = at.split_once(" / ")
+        {
+            if is_various(artist) {
+                self.compilation = true;
+            } else {
+                self.artist =
+                    self.artist.take().or_else(|| Some(artist.to_owned()));
             }
+            self.disc_name =
+                self.disc_name.take().or_else(|| Some(album.to_owned()));
         }
-        self.date = cddb.remove("DYEAR").map(|y| y.parse()).transpose()?;
+        self.date = cddb.remove("DYEAR").and_then(|y| {
+            y.trim()
+                .parse()
+                .inspect_err(|e| warn!("Invalid DYEAR '{y}' in cddb: {e}"))
+                .ok()
+        });
         self.genre = cddb.remove("DGENRE");
 
+        // Titles are numbered from 0. Compilations have titles in the form
+        // `artist / title`.
+        for (i, (t, _)) in self.tracks.iter_mut().enumerate() {
+            let num = t.track.map_or(i, |n| n.saturating_sub(1));
+            let Some(title) = cddb.remove(&format!("TTITLE{num}")) else {
+                continue;
+            };
+            let (artist, title) = match title.split_once(" / ") {
+                Some((a, t)) => (Some(a.to_owned()), t.to_owned()),
+                None => (None, title),
+            };
+            if t.title.is_none() {
+                t.feat = get_perf(&title)
+                    .inspect_err(|e| {
+                        warn!("Failed to parse features from '{title}': {e}")
+                    })
+                    .unwrap_or_default();
+                t.title = Some(title);
+            }
+            t.artist = t.artist.take().or(artist);
+        }
+
         Ok(())
     }
+
+    /// Fills the fields that are not set from CD-TEXT. Texts for the whole
+    /// disc are used for tracks without their own text.
+    fn read_cdtext(&mut self, cdtext_file: &Path) -> Result<()> {
+        let cdtext = CdText::from_file(cdtext_file)?;
+        let get = |kind, track| cdtext.get(kind, track).map(str::to_owned);
+        let get_or_disc =
+            |kind, track| get(kind, track).or_else(|| get(kind, 0));
+
+        self.disc_name =
+            self.disc_name.take().or_else(|| get(CdTextKind::Title, 0));
+        self.artist =
+            self.artist.take().or_else(|| get(CdTextKind::Performer, 0));
+        self.barcode =
+            self.barcode.take().or_else(|| get(CdTextKind::Code, 0));
+
+        for (i, (t, _)) in self.tracks.iter_mut().enumerate() {
+            let num = t.track.unwrap_or(i + 1);
+            if t.title.is_none()
+                && let Some(title) = get(CdTextKind::Title, num)
+            {
+                t.feat = get_perf(&title)
+                    .inspect_err(|e| {
+                        warn!("Failed to parse features from '{title}': {e}")
+                    })
+                    .unwrap_or_default();
+                t.title = Some(title);
+            }
+            t.artist =
+                t.artist.take().or_else(|| get(CdTextKind::Performer, num));
+            t.lyricist = t
+                .lyricist
+                .take()
+                .or_else(|| get_or_disc(CdTextKind::Songwriter, num));
+            t.composer = t
+                .composer
+                .take()
+                .or_else(|| get_or_disc(CdTextKind::Composer, num));
+            t.arranger = t
+                .arranger
+                .take()
+                .or_else(|| get_or_disc(CdTextKind::Arranger, num));
+            t.comment = t
+                .comment
+                .take()
+                .or_else(|| get_or_disc(CdTextKind::Message, num));
+            t.isrc = t.isrc.take().or_else(|| get(CdTextKind::Code, num));
+        }
+
+        Ok(())
+    }
+
+    /// Fills the fields that are not set from the cdindex file.
+    fn read_cdindex(&mut self, cdindex_file: &Path) -> Result<()> {
+        let cdindex = CdIndex::from_file(cdindex_file)?;
+
+        self.cdindex = self.cdindex.take().or_else(|| cdindex.id.clone());
+        self.disc_name =
+            self.disc_name.take().or_else(|| cdindex.title.clone());
+        if !self.compilation {
+            self.artist =
+                self.artist.take().or_else(|| cdindex.artist.clone());
+        }
+
+        for (i, (t, _)) in self.tracks.iter_mut().enumerate() {
+            let num = t.track.unwrap_or(i + 1);
+            let Some(ct) = cdindex.tracks.iter().find(|c| c.num == num) else {
+                continue;
+            };
+            if t.title.is_none()
+                && let Some(name) = &ct.name
+            {
+                t.feat = get_perf(name)
+                    .inspect_err(|e| {
+                        warn!("Failed to parse features from '{name}': {e}")
+                    })
+                    .unwrap_or_default();
+                t.title = Some(name.clone());
+            }
+            t.artist = t.artist.take().or_else(|| ct.artist.clone());
+            t.start = t.start.or(ct.start);
+            t.length = t.length.or_else(|| cdindex.length(num));
+        }
+
+        Ok(())
+    }
+}
+
+/// Gets the track number from file name produced by cdparanoia
+/// (`track01.cdda.wav`).
+fn cdparanoia_track(path: &Path) -> Option<usize> {
+    let name = path.file_name()?.to_str()?.to_ascii_lowercase();
+    let num = name.strip_prefix("track")?.strip_suffix(".cdda.wav")?;
+    if num.is_empty() || !num.bytes().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    num.parse().ok()
+}
+
+/// Finds cue sheet in the directory if it doesn't contain `.inf` files.
+fn find_cue(dir: &Path) -> Result<Option<PathBuf>> {
+    let mut cue = None;
+    for f in fs::read_dir(dir)? {
+        let path = f?.path();
+        match path.extension().and_then(|e| e.to_str()) {
+            Some(e) if e.eq_ignore_ascii_case("inf") => return Ok(None),
+            Some(e) if e.eq_ignore_ascii_case("cue") => cue = Some(path),
+            _ => {}
+        }
+    }
+    Ok(cue)
+}
+
+/// Creates table of contents in the format of the `CDTOC` tag from the
+/// start and length of the tracks in sectors. Sectors are offset by the 2
+/// second lead-in.
+fn cdtoc<I>(tracks: I) -> Option<String>
+where
+    I: IntoIterator<Item = Option<(u32, u32)>>,
+{
+    let toc = tracks.into_iter().collect::<Option<Vec<_>>>()?;
+    let (start, len) = toc.last()?;
+
+    let mut res = format!("{:X}", toc.len());
+    for (s, _) in &toc {
+        res += &format!("+{:X}", s + 150);
+    }
+    res += &format!("+{:X}", start + len + 150);
+    Some(res)
+}
+
+/// Checks whether the artist name is used for compilations.
+fn is_various(artist: &str) -> bool {
+    ["various", "various artists"]
+        .iter()
+        .any(|v| v.eq_ignore_ascii_case(artist.trim()))
 }
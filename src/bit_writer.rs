@@ -0,0 +1,58 @@
+/// Writes values with arbitrary bit widths, most significant bit first.
+#[derive(Default)]
+pub struct BitWriter {
+    pub bytes: Vec<u8>,
+    acc: u64,
+    bits: u32,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.bytes.clear();
+        self.acc = 0;
+        self.bits = 0;
+    }
+
+    /// Writes the lowest `cnt` bits of `value`. `cnt` must be at most 32.
+    pub fn write(&mut self, cnt: u32, value: u64) {
+        if cnt == 0 {
+            return;
+        }
+        self.acc = (self.acc << cnt) | (value & ((1 << cnt) - 1));
+        self.bits += cnt;
+        while self.bits >= 8 {
+            self.bits -= 8;
+            self.bytes.push((self.acc >> self.bits) as u8);
+        }
+    }
+
+    /// Writes signed value in two's complement with `cnt` bits.
+    pub fn write_signed(&mut self, cnt: u32, value: i64) {
+        if cnt > 32 {
+            self.write(cnt - 32, (value >> 32) as u64);
+            self.write(32, value as u64);
+        } else {
+            self.write(cnt, value as u64);
+        }
+    }
+
+    /// Writes `cnt` zeros followed by one.
+    pub fn write_unary(&mut self, mut cnt: u64) {
+        while cnt >= 32 {
+            self.write(32, 0);
+            cnt -= 32;
+        }
+        self.write(cnt as u32 + 1, 1);
+    }
+
+    /// Writes zeros until the output is aligned to byte.
+    pub fn align(&mut self) {
+        if self.bits != 0 {
+            self.write(8 - self.bits, 0);
+        }
+    }
+}
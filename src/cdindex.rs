@@ -0,0 +1,124 @@
+use std::{fs, path::Path};
+
+use roxmltree::{Document, Node, ParsingOptions};
+
+use crate::err::Result;
+
+/// Disc info from the `audio.cdindex` XML file produced by cdda2wav.
+#[derive(Debug, Default)]
+pub struct CdIndex {
+    pub id: Option<String>,
+    pub title: Option<String>,
+    /// Artist of the whole disc. This is set only for single artist discs.
+    pub artist: Option<String>,
+    pub tracks: Vec<CdIndexTrack>,
+    /// Sector after the last track (without the lead-in).
+    pub lead_out: Option<u32>,
+}
+
+#[derive(Debug, Default)]
+pub struct CdIndexTrack {
+    pub num: usize,
+    pub name: Option<String>,
+    pub artist: Option<String>,
+    /// First sector of the track (without the lead-in).
+    pub start: Option<u32>,
+}
+
+impl CdIndex {
+    pub fn from_file<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let data = fs::read(path)?;
+        let data = String::from_utf8(data).unwrap_or_else(|e| {
+            e.into_bytes().into_iter().map(char::from).collect()
+        });
+        // The file has doctype.
+        let doc = Document::parse_with_options(
+            &data,
+            ParsingOptions {
+                allow_dtd: true,
+                ..Default::default()
+            },
+        )?;
+
+        let root = doc.root_element();
+        let mut res = Self {
+            id: child_text(descendant(root, "DiskId"), "Id"),
+            title: child_text(Some(root), "Title"),
+            artist: child_text(descendant(root, "SingleArtistCD"), "Artist"),
+            ..Default::default()
+        };
+
+        for t in root.descendants().filter(|n| n.has_tag_name("Track")) {
+            let Some(num) = t.attribute("Num").and_then(|n| n.parse().ok())
+            else {
+                continue;
+            };
+            res.tracks.push(CdIndexTrack {
+                num,
+                name: child_text(Some(t), "Name"),
+                artist: child_text(Some(t), "Artist"),
+                start: None,
+            });
+        }
+
+        // Offsets include the 2 second lead-in. Offset without number is
+        // the lead-out.
+        for o in root.descendants().filter(|n| n.has_tag_name("Offset")) {
+            let Some(value) = o
+                .attribute("Value")
+                .and_then(|v| v.parse::<u32>().ok())
+                .map(|v| v.saturating_sub(150))
+            else {
+                continue;
+            };
+            let num = o.attribute("Num").and_then(|n| n.parse().ok());
+            match num {
+                Some(num) => {
+                    if let Some(t) =
+                        res.tracks.iter_mut().find(|t| t.num == num)
+                    {
+                        t.start = Some(value);
+                    } else {
+                        res.tracks.push(CdIndexTrack {
+                            num,
+                            start: Some(value),
+                            ..Default::default()
+                        });
+                    }
+                }
+                None => res.lead_out = Some(value),
+            }
+        }
+
+        res.tracks.sort_by_key(|t| t.num);
+        Ok(res)
+    }
+
+    /// Gets the length of the track with the given number in sectors.
+    pub fn length(&self, num: usize) -> Option<u32> {
+        let idx = self.tracks.iter().position(|t| t.num == num)?;
+        let start = self.tracks[idx].start?;
+        let end = match self.tracks.get(idx + 1) {
+            Some(t) => t.start?,
+            None => self.lead_out?,
+        };
+        end.checked_sub(start)
+    }
+}
+
+fn descendant<'a, 'i>(node: Node<'a, 'i>, tag: &str) -> Option<Node<'a, 'i>> {
+    node.descendants().find(|n| n.has_tag_name(tag))
+}
+
+/// Gets the trimmed text of the child element. Empty text is [`None`].
+fn child_text(node: Option<Node>, tag: &str) -> Option<String> {
+    let text = node?
+        .children()
+        .find(|n| n.has_tag_name(tag))?
+        .text()?
+        .trim();
+    (!text.is_empty()).then(|| text.to_owned())
+}
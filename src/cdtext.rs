@@ -0,0 +1,343 @@
+use std::{collections::HashMap, fs, path::Path};
+
+use encoding_rs::{EUC_KR, Encoding, GBK, SHIFT_JIS};
+use log::warn;
+
+use crate::err::Result;
+
+/// Size of single CD-TEXT pack in bytes.
+const PACK_SIZE: usize = 18;
+/// Pack with the character code of the block.
+const SIZE_INFO: u8 = 0x8F;
+
+/// Type of text in CD-TEXT.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum CdTextKind {
+    Title = 0x80,
+    Performer = 0x81,
+    Songwriter = 0x82,
+    Composer = 0x83,
+    Arranger = 0x84,
+    Message = 0x85,
+    /// UPC/EAN of the disc or ISRC of the track.
+    Code = 0x8E,
+}
+
+/// CD-TEXT of disc. It may contain texts in multiple languages.
+#[derive(Debug, Default)]
+pub struct CdText {
+    /// Blocks in the order of their number. Block 0 is the primary language.
+    blocks: Vec<CdTextBlock>,
+}
+
+/// Texts in single language.
+#[derive(Debug, Default)]
+struct CdTextBlock {
+    /// Character code from the size info.
+    charset: u8,
+    /// Texts for the tracks. Track 0 is the whole disc.
+    texts: HashMap<(CdTextKind, usize), String>,
+}
+
+impl CdText {
+    /// Reads raw CD-TEXT packs from file, as dumped by cdda2wav to
+    /// `audio.cdtext`. Packs with invalid CRC are skipped.
+    pub fn from_file<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        Ok(Self::decode(&fs::read(path)?))
+    }
+
+    /// Decodes CD-TEXT from raw packs. The data may start with 4 byte header
+    /// of the READ TOC command.
+    pub fn decode(mut data: &[u8]) -> Self {
+        if data.len() % PACK_SIZE == 4
+            || data.len() >= 4
+                && u16::from_be_bytes([data[0], data[1]]) as usize + 2
+                    == data.len()
+        {
+            data = &data[4..];
+        }
+
+        let packs: Vec<_> = data.chunks_exact(PACK_SIZE).collect();
+        // Some drives don't return the CRC.
+        let no_crc = packs.iter().all(|p| p[16..] == [0, 0]);
+        let mut invalid = 0;
+        let packs: Vec<_> = packs
+            .into_iter()
+            .filter(|p| {
+                let valid = no_crc || crc(&p[..16]) == [p[16], p[17]];
+                invalid += !valid as usize;
+                valid
+            })
+            .collect();
+        if invalid != 0 {
+            warn!("Skipped {invalid} CD-TEXT packs with invalid CRC.");
+        }
+
+        let mut res = Self::default();
+        for b in 0..8 {
+            let block: Vec<_> = packs
+                .iter()
+                .filter(|p| (p[3] >> 4) & 7 == b)
+                .copied()
+                .collect();
+            if !block.is_empty() {
+                res.blocks.push(CdTextBlock::decode(&block));
+            }
+        }
+        res
+    }
+
+    /// Gets the text for the track. Track 0 is the whole disc. The text is
+    /// taken from the first block that contains it.
+    pub fn get(&self, kind: CdTextKind, track: usize) -> Option<&str> {
+        self.blocks
+            .iter()
+            .find_map(|b| b.texts.get(&(kind, track)))
+            .map(|s| s.as_str())
+    }
+}
+
+impl CdTextBlock {
+    fn decode(packs: &[&[u8]]) -> Self {
+        // Size info is split into 3 packs.
+        let mut info: Vec<_> =
+            packs.iter().filter(|p| p[0] == SIZE_INFO).collect();
+        info.sort_by_key(|p| p[1]);
+        let info: Vec<u8> =
+            info.iter().flat_map(|p| &p[4..16]).copied().collect();
+        let mut res = Self {
+            charset: info.first().copied().unwrap_or_default(),
+            texts: HashMap::new(),
+        };
+
+        for kind in [
+            CdTextKind::Title,
+            CdTextKind::Performer,
+            CdTextKind::Songwriter,
+            CdTextKind::Composer,
+            CdTextKind::Arranger,
+            CdTextKind::Message,
+            CdTextKind::Code,
+        ] {
+            let mut packs: Vec<_> =
+                packs.iter().filter(|p| p[0] == kind as u8).collect();
+            packs.sort_by_key(|p| p[2]);
+            res.decode_texts(kind, &packs);
+        }
+        res
+    }
+
+    /// Joins the texts from the packs. Each text is terminated by null
+    /// character and belongs to the track after the previous text.
+    fn decode_texts(&mut self, kind: CdTextKind, packs: &[&&[u8]]) {
+        let mut track = 0;
+        let mut text: Vec<u8> = vec![];
+        let mut prev = String::new();
+        let mut last_seq: Option<u8> = None;
+        // Skip the rest of text whose start was lost.
+        let mut skip = false;
+        for p in packs {
+            let double = p[3] & 0x80 != 0;
+            let width = if double { 2 } else { 1 };
+            // Packs of the same type have consecutive sequence numbers. If
+            // some packs were lost, continue from the track of this pack.
+            if last_seq.map(|s| s.wrapping_add(1)) != Some(p[2]) {
+                text.clear();
+                track = (p[1] & 0x7F) as usize;
+                skip = p[3] & 0x0F != 0;
+            }
+            last_seq = Some(p[2]);
+
+            for c in p[4..16].chunks_exact(width) {
+                if c.iter().any(|b| *b != 0) {
+                    text.extend(c);
+                    continue;
+                }
+                if skip {
+                    skip = false;
+                    text.clear();
+                    track += 1;
+                    continue;
+                }
+
+                // Tab means that the text is the same as for the previous
+                // track.
+                let value =
+                    if text.iter().all(|b| *b == b'\t') && !text.is_empty() {
+                        prev.clone()
+                    } else {
+                        self.decode_string(kind, double, &text)
+                    };
+                if !value.is_empty() {
+                    self.texts.insert((kind, track), value.clone());
+                }
+                prev = value;
+                text.clear();
+                track += 1;
+            }
+        }
+    }
+
+    fn decode_string(
+        &self,
+        kind: CdTextKind,
+        double: bool,
+        text: &[u8],
+    ) -> String {
+        // Codes are always in ASCII.
+        let charset = if kind == CdTextKind::Code {
+            0
+        } else {
+            self.charset
+        };
+        let decode = |encoding: &'static Encoding| {
+            encoding.decode_without_bom_handling(text).0.into_owned()
+        };
+        let res = match charset {
+            0x80 => decode(SHIFT_JIS),
+            0x81 => decode(EUC_KR),
+            0x82 => decode(GBK),
+            // Double byte text without known character code is most likely
+            // MS-JIS.
+            _ if double => decode(SHIFT_JIS),
+            // ISO-8859-1 and ASCII
+            _ => text.iter().map(|b| *b as char).collect(),
+        };
+        res.trim().to_owned()
+    }
+}
+
+/// Computes CRC-16/CCITT of the pack. The CRC is stored inverted.
+fn crc(data: &[u8]) -> [u8; 2] {
+    let mut crc: u16 = 0;
+    for b in data {
+        crc ^= (*b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    (!crc).to_be_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// CD-TEXT of disc with 2 tracks. Block 0 is in ISO-8859-1 and block 1
+    /// is in MS-JIS. Each line is single pack: header, text and CRC.
+    const DUMP: [&str; 14] = [
+        "80 00 00 00  41 6C 62 75 6D 00 46 69 72 73 74 20  3E 60",
+        "80 01 01 06  53 6F 6E 67 00 53 65 63 6F 6E 64 00  0F BC",
+        "81 00 02 00  41 72 74 69 73 74 00 09 00 47 75 65  E2 01",
+        "81 02 03 03  73 74 00 00 00 00 00 00 00 00 00 00  3B 8F",
+        "8E 00 04 00  30 37 32 34 33 38 33 38 34 34 39 32  B2 A4",
+        "8E 00 05 0C  36 00 00 00 00 00 00 00 00 00 00 00  FC 4D",
+        "8F 00 06 00  00 01 02 00 00 00 00 00 00 00 00 00  83 30",
+        "8F 01 07 00  00 00 00 00 00 00 00 00 00 00 00 00  50 6E",
+        "8F 02 08 00  00 00 00 00 00 00 00 00 00 00 00 00  28 26",
+        "80 00 09 90  93 FA 96 7B 00 00 88 EA 00 00 93 F1  D6 C0",
+        "80 03 0A 90  00 00 00 00 00 00 00 00 00 00 00 00  AA 16",
+        "8F 00 0B 10  80 01 02 00 00 00 00 00 00 00 00 00  52 8A",
+        "8F 01 0C 10  00 00 00 00 00 00 00 00 00 00 00 00  20 12",
+        "8F 02 0D 10  00 00 00 00 00 00 00 00 00 00 00 00  B8 56",
+    ];
+
+    /// Joins the packs and prepends the header of READ TOC as in
+    /// `audio.cdtext`.
+    fn dump(packs: &[&str]) -> Vec<u8> {
+        let data: Vec<u8> = packs
+            .iter()
+            .flat_map(|p| p.split_whitespace())
+            .map(|b| u8::from_str_radix(b, 16).unwrap())
+            .collect();
+        let mut res = (data.len() as u16 + 2).to_be_bytes().to_vec();
+        res.extend([0, 0]);
+        res.extend(data);
+        res
+    }
+
+    #[test]
+    fn pack_crc() {
+        // Check value of CRC-16/XMODEM inverted.
+        assert_eq!(crc(b"123456789"), [0xCE, 0x3C]);
+        for p in DUMP {
+            let p = &dump(&[p])[4..];
+            assert_eq!(crc(&p[..16]), [p[16], p[17]]);
+        }
+    }
+
+    #[test]
+    fn decode_dump() {
+        let text = CdText::decode(&dump(&DUMP));
+        assert_eq!(text.blocks.len(), 2);
+        assert_eq!(text.blocks[0].charset, 0);
+        assert_eq!(text.blocks[1].charset, 0x80);
+
+        let get = |kind, track| text.get(kind, track);
+        assert_eq!(get(CdTextKind::Title, 0), Some("Album"));
+        assert_eq!(get(CdTextKind::Title, 1), Some("First Song"));
+        assert_eq!(get(CdTextKind::Title, 2), Some("Second"));
+        assert_eq!(get(CdTextKind::Performer, 0), Some("Artist"));
+        // Tab repeats the previous text.
+        assert_eq!(get(CdTextKind::Performer, 1), Some("Artist"));
+        assert_eq!(get(CdTextKind::Performer, 2), Some("Guest"));
+        assert_eq!(get(CdTextKind::Code, 0), Some("0724383844926"));
+        assert_eq!(get(CdTextKind::Code, 1), None);
+        assert_eq!(get(CdTextKind::Composer, 0), None);
+
+        let jp = &text.blocks[1].texts;
+        assert_eq!(jp[&(CdTextKind::Title, 0)], "日本");
+        assert_eq!(jp[&(CdTextKind::Title, 1)], "一");
+        assert_eq!(jp[&(CdTextKind::Title, 2)], "二");
+    }
+
+    #[test]
+    fn without_header() {
+        let text = CdText::decode(&dump(&DUMP)[4..]);
+        assert_eq!(text.get(CdTextKind::Title, 2), Some("Second"));
+    }
+
+    #[test]
+    fn without_crc() {
+        let mut data = dump(&DUMP);
+        for p in data[4..].chunks_exact_mut(PACK_SIZE) {
+            p[16..].fill(0);
+        }
+        let text = CdText::decode(&data);
+        assert_eq!(text.get(CdTextKind::Title, 2), Some("Second"));
+    }
+
+    #[test]
+    fn invalid_crc() {
+        let mut data = dump(&DUMP);
+        // Changes `Album` to `Alcum`.
+        data[4 + 6] ^= 1;
+        let text = CdText::decode(&data);
+        // The rest of the text from the lost pack is skipped.
+        assert_eq!(text.blocks[0].texts.get(&(CdTextKind::Title, 0)), None);
+        assert_eq!(text.blocks[0].texts.get(&(CdTextKind::Title, 1)), None);
+        assert_eq!(text.get(CdTextKind::Title, 2), Some("Second"));
+        // Missing text is taken from the other block.
+        assert_eq!(text.get(CdTextKind::Title, 0), Some("日本"));
+        assert_eq!(text.get(CdTextKind::Performer, 0), Some("Artist"));
+    }
+
+    #[test]
+    fn lost_pack() {
+        let mut packs = DUMP.to_vec();
+        packs.remove(2);
+        let text = CdText::decode(&dump(&packs));
+        // Text that started in the lost pack is not assigned to wrong track.
+        assert_eq!(text.get(CdTextKind::Performer, 0), None);
+        assert_eq!(text.get(CdTextKind::Performer, 1), None);
+        assert_eq!(text.get(CdTextKind::Performer, 2), None);
+        assert_eq!(text.get(CdTextKind::Title, 1), Some("First Song"));
+    }
+}
@@ -1,18 +1,39 @@
+use std::{env, path::PathBuf, thread};
+
 use log::warn;
-use pareg::Pareg;
+use pareg::{FromArg, Pareg};
 
-use crate::err::{Error, Result};
+use crate::{
+    config::Config,
+    encoder::Format,
+    err::{Error, Result},
+    mp3::Mp3Preset,
+};
 
 pub enum Action {
     Help,
     Encode(String),
+    Tag(String),
 }
 
 #[derive(Default)]
 pub struct Args {
     action: Option<Action>,
-    output: Option<String>,
+    /// Output directories, optionally with their format.
+    outputs: Vec<(Option<Format>, PathBuf)>,
+    jobs: Option<usize>,
+    config: Option<PathBuf>,
+    pub cover: Option<PathBuf>,
+    /// Encoder settings given by `--set` as format, key and value.
+    sets: Vec<(Format, String, String)>,
     pub interactive: bool,
+    pub external: bool,
+    pub force: bool,
+    pub no_verify: bool,
+    /// Raw images of type `BINARY` are in big-endian.
+    pub big_endian: bool,
+    pub format: Format,
+    pub mp3_preset: Mp3Preset,
 }
 
 impl Args {
@@ -23,8 +44,40 @@ impl Args {
         Ok(res)
     }
 
-    pub fn output(&self) -> &str {
-        self.output.as_ref().map_or(".", |o| o.as_ref())
+    /// Gets the output directories with their formats.
+    pub fn outputs(&self) -> Vec<(Format, PathBuf)> {
+        if self.outputs.is_empty() {
+            return vec![(self.format, ".".into())];
+        }
+
+        self.outputs
+            .iter()
+            .map(|(f, d)| (f.unwrap_or(self.format), d.clone()))
+            .collect()
+    }
+
+    pub fn jobs(&self) -> usize {
+        self.jobs.unwrap_or_else(|| {
+            thread::available_parallelism().map_or(1, |n| n.get())
+        })
+    }
+
+    /// Loads the config file and applies the settings from the command
+    /// line.
+    pub fn config(&self) -> Result<Config> {
+        let mut res = match &self.config {
+            Some(p) => Config::from_file(p)?,
+            None => match Config::default_path() {
+                Some(p) if p.exists() => Config::from_file(p)?,
+                _ => Config::default(),
+            },
+        };
+
+        for (format, key, value) in &self.sets {
+            res.encoder_mut(*format).set(*format, key, value)?;
+        }
+
+        Ok(res)
     }
 
     pub fn action(&self) -> &Action {
@@ -36,10 +89,21 @@ impl Args {
             match arg {
                 "-h" | "--help" | "-?" => self.set_help()?,
                 "-e" | "--encode" => self.set_encode(args.next_arg()?)?,
-                "-o" | "--output" => self.output = Some(args.next_arg()?),
+                "-t" | "--tag" => self.set_tag(args.next_arg()?)?,
+                "-o" | "--output" => self.add_output(args.next_arg()?),
                 "-i" | "--interactive" => self.interactive = true,
+                "-j" | "--jobs" => self.jobs = Some(args.next_arg()?),
+                "--external" => self.external = true,
+                "--force" => self.force = true,
+                "--no-verify" => self.no_verify = true,
+                "--big-endian" => self.big_endian = true,
+                "-f" | "--format" => self.format = args.next_arg()?,
+                "--mp3-preset" => self.mp3_preset = args.next_arg()?,
+                "--config" => self.config = Some(args.next_arg()?),
+                "--cover" => self.cover = Some(args.next_arg()?),
+                "-s" | "--set" => self.add_set(args.next_arg()?)?,
                 arg if !arg.starts_with('-') => {
-                    self.output = Some(args.cur_arg()?)
+                    self.add_output(args.cur_arg()?)
                 }
                 _ => {
                     Err(args.err_unknown_argument())?;
@@ -50,6 +114,41 @@ impl Args {
         Ok(())
     }
 
+    /// Adds output in the format `[<format>:]<dir>`.
+    fn add_output(&mut self, out: String) {
+        let (format, dir) = match out.split_once(':') {
+            Some((f, d)) => match Format::from_arg(f) {
+                Ok(f) => (Some(f), d),
+                Err(_) => (None, out.as_str()),
+            },
+            None => (None, out.as_str()),
+        };
+
+        // Shell doesn't expand `~` after the format.
+        let dir = match (dir.strip_prefix("~/"), env::var_os("HOME")) {
+            (Some(d), Some(home)) => PathBuf::from(home).join(d),
+            _ => dir.into(),
+        };
+        self.outputs.push((format, dir));
+    }
+
+    /// Adds encoder setting in the format `<format>.<key>=<value>`.
+    fn add_set(&mut self, set: String) -> Result<()> {
+        let Some((name, value)) = set.split_once('=') else {
+            return Err(Error::InvalidUsage(
+                format!("Missing value in setting '{set}'.").into(),
+            ));
+        };
+        let Some((format, key)) = name.split_once('.') else {
+            return Err(Error::InvalidUsage(
+                format!("Missing format in setting '{set}'.").into(),
+            ));
+        };
+        let format = Format::from_arg(format)?;
+        self.sets.push((format, key.to_owned(), value.to_owned()));
+        Ok(())
+    }
+
     fn set_encode(&mut self, path: String) -> Result<()> {
         if self.action.is_some() {
             Err(Error::InvalidUsage("Multiple actions specified.".into()))
@@ -59,6 +158,15 @@ impl Args {
         }
     }
 
+    fn set_tag(&mut self, path: String) -> Result<()> {
+        if self.action.is_some() {
+            Err(Error::InvalidUsage("Multiple actions specified.".into()))
+        } else {
+            self.action = Some(Action::Tag(path));
+            Ok(())
+        }
+    }
+
     fn set_help(&mut self) -> Result<()> {
         if self.action.is_some() {
             Err(Error::InvalidUsage("Multiple actions specified.".into()))
@@ -71,15 +179,62 @@ impl Args {
     fn validate(&self) -> Result<()> {
         match &self.action {
             None => return Err(Error::InvalidUsage("Missing action.".into())),
-            Some(Action::Help) => {
+            Some(Action::Help | Action::Tag(_)) => {
+                // Tagging is always interactive.
                 if self.interactive {
                     warn!("Useless argument '-i'");
                 }
-                if self.output.is_some() {
+                if !self.outputs.is_empty() {
                     warn!("Useless argument '-o'");
                 }
+                if self.jobs.is_some() {
+                    warn!("Useless argument '-j'");
+                }
+                if self.external {
+                    warn!("Useless argument '--external'");
+                }
+                if self.force {
+                    warn!("Useless argument '--force'");
+                }
+                if self.no_verify {
+                    warn!("Useless argument '--no-verify'");
+                }
+                if self.big_endian {
+                    warn!("Useless argument '--big-endian'");
+                }
+                // Tagging uses the sort names from config.
+                if self.config.is_some()
+                    && matches!(self.action, Some(Action::Help))
+                {
+                    warn!("Useless argument '--config'");
+                }
+                if self.cover.is_some() {
+                    warn!("Useless argument '--cover'");
+                }
+                if !self.sets.is_empty() {
+                    warn!("Useless argument '--set'");
+                }
             }
-            _ => {}
+            _ => {
+                let outputs = self.outputs();
+                let has = |f| outputs.iter().any(|(o, _)| *o == f);
+                if self.external && !has(Format::Flac) {
+                    warn!("Useless argument '--external'");
+                }
+                if self.no_verify && !has(Format::Flac) {
+                    warn!("Useless argument '--no-verify'");
+                }
+                if self.mp3_preset != Mp3Preset::default() && !has(Format::Mp3)
+                {
+                    warn!("Useless argument '--mp3-preset'");
+                }
+            }
+        }
+
+        if self.jobs == Some(0) {
+            return Err(Error::InvalidUsage(
+                "Number of jobs must be at least 1.".into(),
+            ));
         }
 
         Ok(())
@@ -0,0 +1,224 @@
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+};
+
+use ini::{Ini, ParseOption};
+use pareg::FromArg;
+
+use crate::{
+    encoder::Format,
+    err::{Error, Result},
+    tag_map::TagMap,
+};
+
+/// Configuration loaded from the config file.
+#[derive(Default)]
+pub struct Config {
+    pub flac: EncoderSettings,
+    pub opus: EncoderSettings,
+    pub mp3: EncoderSettings,
+    /// Sort names of artists that override the generated sort names. Empty
+    /// sort name marks person whose last name goes first.
+    pub sort_names: HashMap<String, String>,
+    /// Album artist of compilations.
+    pub various_artists: Option<String>,
+    /// Names of the vorbis comments.
+    pub tags: TagMap,
+}
+
+/// Settings of encoder for single output format. Unset values use the
+/// default of the encoder.
+#[derive(Default, Clone)]
+pub struct EncoderSettings {
+    /// Compression level.
+    pub level: Option<u32>,
+    pub block_size: Option<usize>,
+    pub padding: Option<usize>,
+    pub verify: Option<bool>,
+    /// Extra arguments passed to the external encoder.
+    pub args: Vec<String>,
+}
+
+impl Config {
+    /// Loads the config from the given file.
+    pub fn from_file<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        // Quotes are kept so that separators may start or end with space.
+        let ini = Ini::load_from_file_opt(
+            path,
+            ParseOption {
+                enabled_quote: false,
+                ..Default::default()
+            },
+        )?;
+        let mut res = Self::default();
+
+        for (section, props) in ini.iter() {
+            let Some(section) = section else {
+                if let Some((key, _)) = props.iter().next() {
+                    return Err(Error::InvalidConfig(format!(
+                        "Setting '{key}' outside of section."
+                    )));
+                }
+                continue;
+            };
+
+            if section == "sort" {
+                for (name, sort) in props.iter() {
+                    res.sort_names.insert(name.trim().into(), sort.into());
+                }
+                continue;
+            }
+
+            if section == "tags" {
+                for (key, value) in props.iter() {
+                    res.tags.set(key, value)?;
+                }
+                continue;
+            }
+
+            if section == "album" {
+                for (key, value) in props.iter() {
+                    res.set_album(key, value)?;
+                }
+                continue;
+            }
+
+            let format = Format::from_arg(section).map_err(|_| {
+                Error::InvalidConfig(format!("Unknown section '{section}'."))
+            })?;
+            for (key, value) in props.iter() {
+                res.encoder_mut(format).set(format, key, value)?;
+            }
+        }
+
+        Ok(res)
+    }
+
+    /// Gets the default path of the config file.
+    pub fn default_path() -> Option<PathBuf> {
+        let dir = env::var_os("XDG_CONFIG_HOME").map(PathBuf::from).or_else(
+            || env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")),
+        )?;
+        Some(dir.join("cdadd").join("config.ini"))
+    }
+
+    /// Gets the album artist of compilations.
+    pub fn various_artists(&self) -> &str {
+        self.various_artists.as_deref().unwrap_or("Various Artists")
+    }
+
+    /// Sets the setting `key` from the section `album`.
+    fn set_album(&mut self, key: &str, value: &str) -> Result<()> {
+        match key.trim() {
+            "various-artists" => {
+                self.various_artists = Some(value.trim().to_owned())
+            }
+            key => {
+                return Err(Error::InvalidConfig(format!(
+                    "Unknown setting '{key}' for album."
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn encoder(&self, format: Format) -> &EncoderSettings {
+        match format {
+            Format::Flac => &self.flac,
+            Format::Opus => &self.opus,
+            Format::Mp3 => &self.mp3,
+        }
+    }
+
+    pub fn encoder_mut(&mut self, format: Format) -> &mut EncoderSettings {
+        match format {
+            Format::Flac => &mut self.flac,
+            Format::Opus => &mut self.opus,
+            Format::Mp3 => &mut self.mp3,
+        }
+    }
+}
+
+impl EncoderSettings {
+    /// Sets the setting `key` of encoder for the given format.
+    pub fn set(
+        &mut self,
+        format: Format,
+        key: &str,
+        value: &str,
+    ) -> Result<()> {
+        let value = value.trim();
+        let lossless = format == Format::Flac;
+        match key.trim() {
+            "level" => {
+                let max = match format {
+                    Format::Flac => 8,
+                    Format::Opus => 10,
+                    Format::Mp3 => 9,
+                };
+                let level = parse_num(key, value)?;
+                if level > max {
+                    return Err(Error::InvalidConfig(format!(
+                        "Level for {format} must be at most {max}."
+                    )));
+                }
+                self.level = Some(level);
+            }
+            "block-size" if lossless => {
+                let size = parse_num(key, value)?;
+                if !(16..=65535).contains(&size) {
+                    return Err(Error::InvalidConfig(
+                        "Block size must be in range 16 to 65535.".into(),
+                    ));
+                }
+                self.block_size = Some(size);
+            }
+            "padding" if lossless => {
+                let padding = parse_num(key, value)?;
+                if padding >= 1 << 24 {
+                    return Err(Error::InvalidConfig(
+                        "Padding must be less than 16 MiB.".into(),
+                    ));
+                }
+                self.padding = Some(padding);
+            }
+            "verify" if lossless => {
+                self.verify = Some(parse_bool(key, value)?)
+            }
+            "args" => {
+                self.args =
+                    value.split_whitespace().map(String::from).collect()
+            }
+            key => {
+                return Err(Error::InvalidConfig(format!(
+                    "Unknown setting '{key}' for {format}."
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_num<T>(key: &str, value: &str) -> Result<T>
+where
+    T: std::str::FromStr,
+{
+    value.parse().map_err(|_| {
+        Error::InvalidConfig(format!("Invalid number '{value}' for '{key}'."))
+    })
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => Err(Error::InvalidConfig(format!(
+            "Invalid value '{value}' for '{key}'. Expected `true` or `false`."
+        ))),
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    err::{Error, Result},
+    flac_meta::MAX_BLOCK_SIZE,
+};
+
+/// Names of files that are used as cover if they are in the source folder.
+const COVER_NAMES: &[&str] = &["cover", "folder", "front"];
+const COVER_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png"];
+
+/// Front cover image.
+#[derive(Debug)]
+pub struct Cover {
+    pub path: PathBuf,
+    pub data: Vec<u8>,
+    pub mime: &'static str,
+    pub width: u32,
+    pub height: u32,
+    /// Bits per pixel.
+    pub depth: u32,
+}
+
+impl Cover {
+    /// Loads the cover from the given image file. Only jpeg and png are
+    /// supported.
+    pub fn from_file<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let data = fs::read(path)?;
+        let (mime, width, height, depth) = if data.starts_with(PNG_SIGNATURE) {
+            png_info(&data)?
+        } else if data.starts_with(&[0xFF, 0xD8]) {
+            jpeg_info(&data)?
+        } else {
+            return Err(Error::InvalidImage(
+                "Only jpeg and png are supported.",
+            ));
+        };
+
+        if width == 0 || height == 0 {
+            return Err(Error::InvalidImage("Image has no pixels."));
+        }
+        // Picture block has 32 bytes of fields and the mime type.
+        if data.len() + 32 + mime.len() > MAX_BLOCK_SIZE {
+            return Err(Error::InvalidImage(
+                "Image is too large to be embedded in flac (16 MiB).",
+            ));
+        }
+
+        Ok(Self {
+            path: path.to_owned(),
+            data,
+            mime,
+            width,
+            height,
+            depth,
+        })
+    }
+
+    /// Finds cover image in the given directory.
+    pub fn find(dir: &Path) -> Option<PathBuf> {
+        let mut files: Vec<_> = fs::read_dir(dir)
+            .ok()?
+            .flatten()
+            .map(|f| f.path())
+            .filter(|p| {
+                let name = p.file_stem().and_then(|n| n.to_str());
+                let ext = p.extension().and_then(|e| e.to_str());
+                name.zip(ext).is_some_and(|(n, e)| {
+                    COVER_NAMES.iter().any(|c| c.eq_ignore_ascii_case(n))
+                        && COVER_EXTENSIONS
+                            .iter()
+                            .any(|c| c.eq_ignore_ascii_case(e))
+                })
+            })
+            .collect();
+
+        // Prefer the names in the order of `COVER_NAMES`.
+        files.sort_by_key(|p| {
+            let name = p.file_stem().and_then(|n| n.to_str()).unwrap_or("");
+            COVER_NAMES
+                .iter()
+                .position(|c| c.eq_ignore_ascii_case(name))
+        });
+        files.into_iter().next()
+    }
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+fn png_info(data: &[u8]) -> Result<(&'static str, u32, u32, u32)> {
+    // Signature, chunk length and chunk type are followed by IHDR.
+    let Some(ihdr) = data.get(8..29) else {
+        return Err(Error::InvalidImage("Png is too short."));
+    };
+    if &ihdr[4..8] != b"IHDR" {
+        return Err(Error::InvalidImage("Missing png header."));
+    }
+
+    let width = u32::from_be_bytes(ihdr[8..12].try_into().unwrap());
+    let height = u32::from_be_bytes(ihdr[12..16].try_into().unwrap());
+    let bits = ihdr[16] as u32;
+    let channels = match ihdr[17] {
+        0 | 3 => 1,
+        4 => 2,
+        2 => 3,
+        6 => 4,
+        _ => return Err(Error::InvalidImage("Invalid png color type.")),
+    };
+
+    Ok(("image/png", width, height, bits * channels))
+}
+
+fn jpeg_info(data: &[u8]) -> Result<(&'static str, u32, u32, u32)> {
+    let mut pos = 2;
+    while let Some(seg) = data.get(pos..pos + 4) {
+        if seg[0] != 0xFF {
+            return Err(Error::InvalidImage("Invalid jpeg segment."));
+        }
+
+        let marker = seg[1];
+        let len = u16::from_be_bytes([seg[2], seg[3]]) as usize;
+        // Start of frame, except DHT, JPG and DAC that share the range.
+        if (0xC0..=0xCF).contains(&marker)
+            && !matches!(marker, 0xC4 | 0xC8 | 0xCC)
+        {
+            let Some(sof) = data.get(pos + 4..pos + 10) else {
+                break;
+            };
+            let bits = sof[0] as u32;
+            let height = u16::from_be_bytes([sof[1], sof[2]]) as u32;
+            let width = u16::from_be_bytes([sof[3], sof[4]]) as u32;
+            let components = sof[5] as u32;
+            return Ok(("image/jpeg", width, height, bits * components));
+        }
+
+        pos += 2 + len;
+    }
+
+    Err(Error::InvalidImage("Missing jpeg frame header."))
+}
@@ -0,0 +1,404 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::warn;
+
+use crate::{
+    date::Date,
+    err::{Error, Result},
+};
+
+/// Number of frames (sectors) per second in cue sheet positions.
+pub const FRAMES_PER_SECOND: u64 = 75;
+
+/// Cue sheet that describes the tracks in audio image.
+#[derive(Debug, Default)]
+pub struct Cue {
+    /// Media catalog number.
+    pub catalog: Option<String>,
+    pub performer: Option<String>,
+    pub title: Option<String>,
+    pub songwriter: Option<String>,
+    pub genre: Option<String>,
+    pub date: Option<Date>,
+    pub discid: Option<u32>,
+    pub files: Vec<CueFile>,
+    /// Tracks in all the files. Track may span multiple files (e.g. when the
+    /// gap is appended to the previous file).
+    pub tracks: Vec<CueTrack>,
+}
+
+/// Audio file referenced by the cue sheet.
+#[derive(Debug)]
+pub struct CueFile {
+    pub path: PathBuf,
+    pub kind: FileKind,
+}
+
+/// Type of file in cue sheet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileKind {
+    /// Wav (or flac) file.
+    Wave,
+    /// Raw 16-bit stereo samples in little-endian.
+    Binary,
+    /// Raw 16-bit stereo samples in big-endian.
+    Motorola,
+}
+
+#[derive(Debug, Default)]
+pub struct CueTrack {
+    pub number: usize,
+    pub title: Option<String>,
+    pub performer: Option<String>,
+    pub songwriter: Option<String>,
+    pub isrc: Option<String>,
+    pub pre_emphasis: bool,
+    /// Data track (`MODE1/2352` or `MODE2/2352`).
+    pub data: bool,
+    pub indices: Vec<CueIndex>,
+}
+
+/// Position of index in the files.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CueIndex {
+    pub number: u32,
+    /// Index of the file in [`Cue::files`].
+    pub file: usize,
+    /// Position in the file in frames.
+    pub pos: u64,
+}
+
+impl Cue {
+    /// Reads the cue sheet from file. Paths of the audio files are relative
+    /// to the cue sheet.
+    pub fn from_file<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let data = decode(fs::read(path)?);
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Self::parse(&data, dir)
+    }
+
+    fn parse(data: &str, dir: &Path) -> Result<Self> {
+        let mut res = Self::default();
+        for (i, line) in data.lines().enumerate() {
+            res.parse_line(&tokens(line), dir).map_err(|msg| {
+                Error::InvalidCue(format!("{msg} (line {})", i + 1))
+            })?;
+        }
+
+        for t in &res.tracks {
+            if t.start().is_none() {
+                return Err(Error::InvalidCue(format!(
+                    "Track {} has no index 1.",
+                    t.number
+                )));
+            }
+        }
+        Ok(res)
+    }
+
+    /// Parses single line split into arguments. Returns error message if
+    /// the line is invalid.
+    fn parse_line(
+        &mut self,
+        args: &[String],
+        dir: &Path,
+    ) -> std::result::Result<(), &'static str> {
+        let Some(cmd) = args.first() else {
+            return Ok(());
+        };
+        let arg = |n: usize| args.get(n).cloned().ok_or("Missing argument.");
+
+        // Track continues in the next file until there is new track.
+        let track = self.tracks.last_mut();
+        match cmd.to_ascii_uppercase().as_str() {
+            "REM" => {
+                if let [_, key, value, ..] = args {
+                    self.parse_rem(key, value);
+                }
+            }
+            "CATALOG" => self.catalog = Some(arg(1)?),
+            "PERFORMER" => match track {
+                Some(t) => t.performer = Some(arg(1)?),
+                None => self.performer = Some(arg(1)?),
+            },
+            "TITLE" => match track {
+                Some(t) => t.title = Some(arg(1)?),
+                None => self.title = Some(arg(1)?),
+            },
+            "SONGWRITER" => match track {
+                Some(t) => t.songwriter = Some(arg(1)?),
+                None => self.songwriter = Some(arg(1)?),
+            },
+            "FILE" => {
+                let kind = match arg(2)?.to_ascii_uppercase().as_str() {
+                    "WAVE" => FileKind::Wave,
+                    "BINARY" => FileKind::Binary,
+                    "MOTOROLA" => FileKind::Motorola,
+                    _ => return Err("Unsupported file type."),
+                };
+                self.files.push(CueFile {
+                    path: dir.join(arg(1)?),
+                    kind,
+                });
+            }
+            "TRACK" => {
+                if self.files.is_empty() {
+                    return Err("Track is not in file.");
+                }
+                // Only raw sectors can be split at the same positions as
+                // audio.
+                let data = match arg(2)?.to_ascii_uppercase().as_str() {
+                    "AUDIO" => false,
+                    "MODE1/2352" | "MODE2/2352" => true,
+                    _ => return Err("Only 2352 byte sectors are supported."),
+                };
+                let number =
+                    arg(1)?.parse().map_err(|_| "Invalid track number.")?;
+                self.tracks.push(CueTrack {
+                    number,
+                    data,
+                    ..Default::default()
+                });
+            }
+            "INDEX" => {
+                let Some(t) = track else {
+                    return Err("Index is not in track.");
+                };
+                let number = arg(1)?.parse().map_err(|_| "Invalid index.")?;
+                let pos = parse_time(&arg(2)?).ok_or("Invalid position.")?;
+                t.indices.push(CueIndex {
+                    number,
+                    file: self.files.len() - 1,
+                    pos,
+                });
+            }
+            "ISRC" => {
+                if let Some(t) = track {
+                    t.isrc = Some(arg(1)?);
+                }
+            }
+            "FLAGS" => {
+                if let Some(t) = track {
+                    t.pre_emphasis =
+                        args.iter().any(|a| a.eq_ignore_ascii_case("PRE"));
+                }
+            }
+            // PREGAP, POSTGAP and CDTEXTFILE don't affect the audio in the
+            // file.
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Parses the comments that contain album info.
+    fn parse_rem(&mut self, key: &str, value: &str) {
+        match key.to_ascii_uppercase().as_str() {
+            "GENRE" => self.genre = Some(value.to_owned()),
+            "DATE" => {
+                self.date = value
+                    .parse()
+                    .inspect_err(|e| warn!("Invalid date '{value}': {e}"))
+                    .ok()
+            }
+            "DISCID" => {
+                let id = value.trim_start_matches("0x");
+                self.discid = u32::from_str_radix(id, 16)
+                    .inspect_err(|e| {
+                        warn!("Invalid disc id '{value}' in cue: {e}")
+                    })
+                    .ok()
+            }
+            _ => {}
+        }
+    }
+}
+
+impl CueTrack {
+    /// Gets the position of index 1.
+    pub fn start(&self) -> Option<CueIndex> {
+        self.indices.iter().find(|i| i.number == 1).copied()
+    }
+}
+
+/// Decodes the cue sheet. Cue sheets are often not in utf-8, so latin-1 is
+/// used if the data are not valid utf-8.
+fn decode(data: Vec<u8>) -> String {
+    match String::from_utf8(data) {
+        Ok(s) => match s.strip_prefix('\u{FEFF}') {
+            Some(s) => s.to_owned(),
+            None => s,
+        },
+        Err(e) => e.into_bytes().into_iter().map(char::from).collect(),
+    }
+}
+
+/// Parses time in the format `mm:ss:ff` into frames.
+fn parse_time(time: &str) -> Option<u64> {
+    let mut parts = time.split(':').map(|p| p.parse::<u64>().ok());
+    let (m, s, f) = (parts.next()??, parts.next()??, parts.next()??);
+    if parts.next().is_some() || s >= 60 || f >= FRAMES_PER_SECOND {
+        return None;
+    }
+    Some((m * 60 + s) * FRAMES_PER_SECOND + f)
+}
+
+/// Splits the line into arguments. Arguments may be quoted.
+fn tokens(line: &str) -> Vec<String> {
+    let mut res = vec![];
+    let mut rest = line.trim();
+    while !rest.is_empty() {
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let (arg, r) = quoted.split_once('"').unwrap_or((quoted, ""));
+            res.push(arg.to_owned());
+            rest = r.trim_start();
+        } else {
+            let (arg, r) =
+                rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            res.push(arg.to_owned());
+            rest = r.trim_start();
+        }
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SINGLE: &str = r#"REM GENRE Rock
+REM DATE 1999
+REM DISCID 1A02B903
+REM COMMENT "ExactAudioCopy v1.0"
+CATALOG 0123456789012
+PERFORMER "The Band"
+TITLE "Cue Album"
+FILE "image.bin" BINARY
+  TRACK 01 MODE1/2352
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE "Second"
+    PERFORMER "Other Artist"
+    ISRC USAAA9900001
+    FLAGS DCP PRE
+    INDEX 00 01:07:00
+    INDEX 01 01:08:10
+    INDEX 02 01:09:74
+"#;
+
+    /// Cue sheet from EAC with gaps appended to the previous file.
+    const MULTI: &str = r#"PERFORMER "The Band"
+FILE "01.wav" WAVE
+  TRACK 01 AUDIO
+    TITLE "First"
+    INDEX 01 00:00:00
+  TRACK 02 AUDIO
+    TITLE "Second"
+    INDEX 00 04:10:20
+FILE "02.wav" WAVE
+    INDEX 01 00:00:00
+  TRACK 03 AUDIO
+    TITLE "Third"
+    INDEX 01 03:00:00
+"#;
+
+    fn index(number: u32, file: usize, pos: u64) -> CueIndex {
+        CueIndex { number, file, pos }
+    }
+
+    #[test]
+    fn single_file() {
+        let cue = Cue::parse(SINGLE, Path::new("dir")).unwrap();
+        assert_eq!(cue.genre.as_deref(), Some("Rock"));
+        assert_eq!(cue.date, Some("1999".parse().unwrap()));
+        assert_eq!(cue.discid, Some(0x1A02B903));
+        assert_eq!(cue.catalog.as_deref(), Some("0123456789012"));
+        assert_eq!(cue.performer.as_deref(), Some("The Band"));
+        assert_eq!(cue.title.as_deref(), Some("Cue Album"));
+
+        assert_eq!(cue.files.len(), 1);
+        assert_eq!(cue.files[0].path, Path::new("dir/image.bin"));
+        assert_eq!(cue.files[0].kind, FileKind::Binary);
+
+        assert_eq!(cue.tracks.len(), 2);
+        let [data, t] = &cue.tracks[..] else {
+            unreachable!()
+        };
+        assert!(data.data);
+        assert_eq!(data.title, None);
+        assert!(!t.data);
+        assert_eq!(t.number, 2);
+        assert_eq!(t.title.as_deref(), Some("Second"));
+        assert_eq!(t.performer.as_deref(), Some("Other Artist"));
+        assert_eq!(t.isrc.as_deref(), Some("USAAA9900001"));
+        assert!(t.pre_emphasis);
+        assert_eq!(
+            t.indices,
+            [index(0, 0, 5025), index(1, 0, 5110), index(2, 0, 5249)]
+        );
+        assert_eq!(t.start(), Some(index(1, 0, 5110)));
+    }
+
+    #[test]
+    fn multiple_files() {
+        let cue = Cue::parse(MULTI, Path::new("")).unwrap();
+        assert_eq!(cue.files.len(), 2);
+        assert_eq!(cue.files[1].path, Path::new("02.wav"));
+        assert_eq!(cue.tracks.len(), 3);
+        assert_eq!(cue.tracks[1].title.as_deref(), Some("Second"));
+        assert_eq!(
+            cue.tracks[1].indices,
+            [index(0, 0, 18770), index(1, 1, 0)]
+        );
+        assert_eq!(cue.tracks[2].start(), Some(index(1, 1, 13500)));
+    }
+
+    #[test]
+    fn invalid() {
+        let err = |data: &str| match Cue::parse(data, Path::new("")) {
+            Err(Error::InvalidCue(msg)) => msg,
+            r => panic!("Expected invalid cue, got {r:?}"),
+        };
+        assert_eq!(
+            err("FILE a.wav WAVE\nINDEX 01 00:00:00"),
+            "Index is not in track. (line 2)"
+        );
+        assert_eq!(err("TRACK 01 AUDIO"), "Track is not in file. (line 1)");
+        assert_eq!(
+            err("FILE a.wav WAVE\nTRACK 01 MODE1/2048"),
+            "Only 2352 byte sectors are supported. (line 2)"
+        );
+        assert_eq!(
+            err("FILE a.wav WAVE\nTRACK 01 AUDIO\nINDEX 01 00:60:00"),
+            "Invalid position. (line 3)"
+        );
+        assert_eq!(
+            err("FILE a.wav WAVE\nTRACK 01 AUDIO\nINDEX 00 00:00:00"),
+            "Track 1 has no index 1."
+        );
+    }
+
+    #[test]
+    fn encoding() {
+        let bom = b"\xEF\xBB\xBFTITLE \"P\xC5\x99\xC3\xADliv\"".to_vec();
+        assert_eq!(decode(bom), "TITLE \"Příliv\"");
+        let latin1 = b"TITLE \"Caf\xE9\"".to_vec();
+        assert_eq!(decode(latin1), "TITLE \"Café\"");
+    }
+
+    #[test]
+    fn split_tokens() {
+        assert_eq!(
+            tokens("  FILE \"my file.wav\"  WAVE "),
+            ["FILE", "my file.wav", "WAVE"]
+        );
+        assert_eq!(tokens("TITLE \"\""), ["TITLE", ""]);
+        assert!(tokens("   ").is_empty());
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use filesan::{Mode, replace_escape};
+use log::{error, warn};
+
+use crate::{
+    album_info::AlbumInfo,
+    encoder::{Encoder, Resume},
+    err::Result,
+    pool,
+    progress::Progress,
+    summary::{Outcome, Summary, TrackResult},
+    track_info::TrackInfo,
+};
+
+/// Output directory with the encoder used for it.
+pub struct Target {
+    pub dir: PathBuf,
+    pub encoder: Box<dyn Encoder>,
+    /// Check the output against the source if the format is lossless.
+    pub verify: bool,
+}
+
+/// Encodes all the tracks in the album into all the targets. Existing files
+/// are reused if possible unless `force` is set.
+pub fn encode(
+    album: &AlbumInfo,
+    targets: &[Target],
+    jobs: usize,
+    force: bool,
+) -> Result<Summary> {
+    let mut summary = Summary::default();
+    for t in targets {
+        fs::create_dir_all(&t.dir)?;
+        let verify = t.verify && t.encoder.can_verify();
+        let verify = if verify { ", verify" } else { "" };
+        summary
+            .settings
+            .push((t.dir.clone(), t.encoder.describe() + verify));
+    }
+
+    let tasks: Vec<_> = album
+        .tracks
+        .iter()
+        .enumerate()
+        .flat_map(|(i, (t, p))| {
+            targets.iter().map(move |d| {
+                let name = file_name(i, t, d.encoder.extension());
+                (t.track.unwrap_or(i + 1), t, p, d.dir.join(name), d)
+            })
+        })
+        .collect();
+
+    let progress = Progress::new(tasks.iter().map(|(_, _, p, dst, _)| {
+        let size = fs::metadata(p).map_or(0, |m| m.len());
+        (dst.to_string_lossy().into_owned(), size)
+    }));
+
+    pool::run(
+        jobs,
+        tasks.into_iter().enumerate(),
+        |(id, (track, t, p, path, dst))| {
+            progress.start(id);
+            // New files are encoded into temporary file, so that existing
+            // file is never damaged.
+            let tmp = part_path(&path);
+            let mut res = encode_track(
+                dst.encoder.as_ref(),
+                p,
+                &path,
+                &tmp,
+                t,
+                force,
+                &|b| progress.update(id, b),
+            );
+
+            let mut verified = None;
+            if dst.verify && res.is_ok() && dst.encoder.can_verify() {
+                progress.verify(id);
+                let out = match res {
+                    Ok(Outcome::Encoded) => &tmp,
+                    _ => &path,
+                };
+                if let Err(e) = dst.encoder.verify(p, out) {
+                    res = Err(e);
+                    verified = Some(false);
+                } else {
+                    verified = Some(true);
+                }
+            }
+
+            if let Ok(Outcome::Encoded) = res
+                && let Err(e) = fs::rename(&tmp, &path)
+            {
+                res = Err(e.into());
+            }
+            if res.is_err()
+                && tmp.exists()
+                && let Err(e) = fs::remove_file(&tmp)
+            {
+                warn!("Failed to remove partial file {tmp:?}: {e}");
+            }
+
+            if res.is_ok()
+                && let Some(lrc) =
+                    t.lyrics.as_ref().and_then(|l| l.lrc.as_ref())
+                && let Err(e) = fs::copy(lrc, path.with_extension("lrc"))
+            {
+                warn!("Failed to copy lyrics {lrc:?}: {e}");
+            }
+
+            (
+                id,
+                TrackResult {
+                    track,
+                    path,
+                    res,
+                    verified,
+                },
+            )
+        },
+        |(id, r)| {
+            progress.finish(id, r.res.as_ref().ok().copied());
+            if let Err(e) = &r.res {
+                error!("Failed to encode {:?}: {e}", r.path);
+            }
+            summary.push(r);
+        },
+    );
+    progress.end();
+
+    Ok(summary)
+}
+
+/// Encodes the track into `dst`. If the file must be encoded again, it is
+/// encoded into `tmp` and [`Outcome::Encoded`] is returned.
+fn encode_track(
+    encoder: &dyn Encoder,
+    src: &Path,
+    dst: &Path,
+    tmp: &Path,
+    track: &TrackInfo,
+    force: bool,
+    progress: &dyn Fn(u64),
+) -> Result<Outcome> {
+    if !force && dst.exists() {
+        match encoder.resume(src, dst, track) {
+            Ok(Resume::Skip) => return Ok(Outcome::Skipped),
+            Ok(Resume::Retag) => {
+                encoder.retag(dst, track)?;
+                return Ok(Outcome::Retagged);
+            }
+            Ok(Resume::Encode) => {}
+            Err(e) => warn!("Failed to check existing file {dst:?}: {e}"),
+        }
+    }
+
+    encoder.encode(src, tmp, track, progress)?;
+    Ok(Outcome::Encoded)
+}
+
+/// Gets path of the temporary file that is used while encoding into `path`.
+fn part_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{name}.part"))
+}
+
+fn file_name(i: usize, t: &TrackInfo, ext: &str) -> String {
+    match (t.track, &t.title) {
+        (Some(n), Some(t)) => {
+            replace_escape(&format!("{n:02}. {t}.{ext}"), '-', Mode::ALL)
+        }
+        (Some(n), None) => format!("{n:02}.{ext}"),
+        (None, Some(t)) => replace_escape(
+            &format!("{:02}. {t}.{ext}", i + 1),
+            '-',
+            Mode::all(),
+        ),
+        _ => format!("{:02}.{ext}", i + 1),
+    }
+}
@@ -0,0 +1,211 @@
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{self, Read, Write},
+    path::Path,
+    process::{Command, Stdio},
+    thread,
+};
+
+use log::{error, warn};
+use pareg::FromArg;
+
+use crate::{
+    cli::Args,
+    config::Config,
+    err::{Error, Result},
+    flac::Flac,
+    mp3::Mp3,
+    opus::Opus,
+    track_info::TrackInfo,
+};
+
+/// Encodes tracks into single output format.
+pub trait Encoder: Sync {
+    /// Extension of the produced files (without the dot).
+    fn extension(&self) -> &'static str;
+
+    /// Describes the format and the settings used by the encoder.
+    fn describe(&self) -> String;
+
+    /// Encodes the wav file `src` into `dst` and tags it with the metadata
+    /// from `track`. `progress` is called with the number of bytes of `src`
+    /// that were already processed.
+    fn encode(
+        &self,
+        src: &Path,
+        dst: &Path,
+        track: &TrackInfo,
+        progress: &dyn Fn(u64),
+    ) -> Result<()>;
+
+    /// Decides what to do with the already existing file `dst` that was
+    /// encoded from `src`. By default the file is encoded again.
+    fn resume(
+        &self,
+        _src: &Path,
+        _dst: &Path,
+        _track: &TrackInfo,
+    ) -> Result<Resume> {
+        Ok(Resume::Encode)
+    }
+
+    /// Replaces the metadata of the already encoded file `dst` with the
+    /// metadata from `track`. This is called only if [`Encoder::resume`]
+    /// returns [`Resume::Retag`].
+    fn retag(&self, _dst: &Path, _track: &TrackInfo) -> Result<()> {
+        Err(Error::RetagUnsupported)
+    }
+
+    /// Checks whether the encoded files can be verified. This is possible
+    /// only for lossless formats.
+    fn can_verify(&self) -> bool {
+        false
+    }
+
+    /// Checks that `dst` decodes to the same audio as `src`. This is called
+    /// only if [`Encoder::can_verify`] returns `true`.
+    fn verify(&self, _src: &Path, _dst: &Path) -> Result<()> {
+        Err(Error::VerifyUnsupported)
+    }
+}
+
+/// What to do with already existing output file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resume {
+    /// The file is identical to what would be produced.
+    Skip,
+    /// The audio is the same, but the metadata differ.
+    Retag,
+    Encode,
+}
+
+/// Output format.
+#[derive(FromArg, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Flac,
+    Opus,
+    Mp3,
+}
+
+impl Format {
+    /// Creates encoder for this format configured by the arguments and the
+    /// config.
+    pub fn encoder(self, args: &Args, config: &Config) -> Box<dyn Encoder> {
+        let settings = config.encoder(self).clone();
+        match self {
+            Self::Flac => {
+                if !args.external && !settings.args.is_empty() {
+                    warn!(
+                        "Flac args from the config are ignored by the \
+                        built-in encoder. Use --external to use them."
+                    );
+                }
+                Box::new(Flac {
+                    external: args.external,
+                    settings,
+                    tags: config.tags.clone(),
+                })
+            }
+            Self::Opus => Box::new(Opus {
+                settings,
+                tags: config.tags.clone(),
+            }),
+            Self::Mp3 => Box::new(Mp3 {
+                preset: args.mp3_preset,
+                settings,
+            }),
+        }
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Flac => write!(f, "flac"),
+            Self::Opus => write!(f, "opus"),
+            Self::Mp3 => write!(f, "mp3"),
+        }
+    }
+}
+
+/// Describes extra arguments of external encoder.
+pub fn describe_args(args: &[String]) -> String {
+    if args.is_empty() {
+        String::new()
+    } else {
+        format!(", args `{}`", args.join(" "))
+    }
+}
+
+/// Runs external encoder and feeds `src` to its stdin. Output of the encoder
+/// is logged only if it fails.
+pub fn run(
+    cmd: &mut Command,
+    src: &Path,
+    progress: &dyn Fn(u64),
+) -> Result<()> {
+    let mut file = File::open(src)?;
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let mut stdin = child.stdin.take().unwrap();
+    let mut stderr = child.stderr.take().unwrap();
+
+    let (fed, err) = thread::scope(|s| {
+        // Stderr must be read at the same time, so that the encoder doesn't
+        // block on full pipe.
+        let err = s.spawn(move || {
+            let mut err = vec![];
+            _ = stderr.read_to_end(&mut err);
+            err
+        });
+        let fed = feed(&mut file, &mut stdin, progress);
+        drop(stdin);
+        (fed, err.join().unwrap_or_default())
+    });
+    let status = child.wait()?;
+
+    if status.success() {
+        // The encoder may not read data after the audio (e.g. trailing wav
+        // chunks).
+        return match fed {
+            Err(e) if e.kind() != io::ErrorKind::BrokenPipe => Err(e.into()),
+            _ => Ok(()),
+        };
+    }
+
+    let msg = String::from_utf8_lossy(&err);
+    if !msg.trim().is_empty() {
+        error!("{}", msg.trim());
+    }
+    match status.code() {
+        Some(c) => Err(Error::EncoderExit(c)),
+        None => Err(Error::EncoderKilled),
+    }
+}
+
+fn feed<R, W>(
+    src: &mut R,
+    dst: &mut W,
+    progress: &dyn Fn(u64),
+) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+{
+    let mut buf = vec![0; 64 * 1024];
+    let mut total = 0;
+    loop {
+        let len = src.read(&mut buf)?;
+        if len == 0 {
+            return Ok(());
+        }
+        dst.write_all(&buf[..len])?;
+        total += len as u64;
+        progress(total);
+    }
+}
@@ -8,15 +8,45 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub enum Error {
     #[error("Invalid usage: {0}")]
     InvalidUsage(Cow<'static, str>),
+    #[error("Invalid config: {0}")]
+    InvalidConfig(String),
     #[error("Failed to parse date: invalid date format")]
     ParseDate,
     #[error("Failed to parse cddb file.")]
     ParseCddb,
     #[error("Failed to parse featuring from track name: {0}")]
     ParseFeat(&'static str),
+    #[error("Invalid wav file: {0}")]
+    InvalidWav(&'static str),
+    #[error("Invalid image: {0}")]
+    InvalidImage(&'static str),
+    #[error("Invalid cue sheet: {0}")]
+    InvalidCue(String),
+    #[error("Invalid flac file: {0}")]
+    InvalidFlac(&'static str),
+    #[error("Unknown channel layout with {0} channels.")]
+    UnknownLayout(u16),
+    #[error("Encoder doesn't support retagging.")]
+    RetagUnsupported,
+    #[error("Encoder doesn't support verification.")]
+    VerifyUnsupported,
+    #[error("Decoded audio doesn't match the source.")]
+    VerifyFailed,
+    #[error("Encoder exited with code {0}.")]
+    EncoderExit(i32),
+    #[error("Encoder was terminated.")]
+    EncoderKilled,
+    #[error("Failed to encode {0} of {1} files.")]
+    EncodeFailed(usize, usize),
+    #[error("Failed to tag {0} of {1} files.")]
+    TagFailed(usize, usize),
+    #[error(transparent)]
+    Claxon(#[from] claxon::Error),
     #[error(transparent)]
     Logger(#[from] flexi_logger::FlexiLoggerError),
     #[error(transparent)]
+    Id3(#[from] id3::Error),
+    #[error(transparent)]
     Ini(#[from] ini::Error),
     #[error(transparent)]
     Io(#[from] std::io::Error),
@@ -25,5 +55,7 @@ pub enum Error {
     #[error(transparent)]
     Pareg(#[from] pareg::ArgError),
     #[error(transparent)]
+    Xml(#[from] roxmltree::Error),
+    #[error(transparent)]
     Termal(#[from] termal::error::Error),
 }
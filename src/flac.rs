@@ -1,87 +1,260 @@
-use std::{fmt::Display, fs, path::Path, process::Command};
+use std::{ffi::OsString, path::Path, process::Command};
 
-use filesan::{Mode, replace_escape};
-use log::error;
+use claxon::FlacReader;
+use md5::{Digest, Md5};
 
-use crate::{album_info::AlbumInfo, err::Result, track_info::TrackInfo};
+use crate::{
+    config::EncoderSettings,
+    encoder::{self, Encoder, Resume},
+    err::{Error, Result},
+    flac_enc::{self, FlacSettings},
+    flac_meta::{
+        self, BLOCK_CUESHEET, BLOCK_PICTURE, BLOCK_VORBIS_COMMENT, Block,
+        FlacMeta,
+    },
+    tag_map::TagMap,
+    tags,
+    track_info::TrackInfo,
+    wav::WavReader,
+};
 
-pub fn encode<P>(album: &AlbumInfo, dst: P) -> Result<()>
-where
-    P: AsRef<Path>,
-{
-    fs::create_dir_all(&dst)?;
-    let mut tasks = vec![];
-    for (i, (t, p)) in album.tracks.iter().enumerate() {
-        let mut cmd = Command::new("flac");
-        cmd.arg(p);
-        cmd.args(["--best", "-o"]);
-        cmd.arg(dst.as_ref().join(match (t.track, &t.title) {
-            (Some(n), Some(t)) => {
-                replace_escape(&format!("{n:02}. {t}.flac"), '-', Mode::ALL)
-            }
-            (Some(n), None) => format!("{n:02}.flac"),
-            (None, Some(t)) => replace_escape(
-                &format!("{:02}. {t}.flac", i + 1),
-                '-',
-                Mode::all(),
-            ),
-            _ => format!("{:02}.flac", i + 1),
-        }));
-        add_metadata(&mut cmd, t);
-        tasks.push(cmd.spawn());
-    }
-
-    for t in tasks {
-        match t.and_then(|mut t| t.wait()) {
-            Ok(o) => {
-                if !o.success() {
-                    if let Some(c) = o.code() {
-                        error!("Failed to encode: {c}");
-                    } else {
-                        error!("Failed to encode");
-                    }
-                }
-            }
-            Err(e) => {
-                error!("Failed to encode: {e}");
-            }
-        }
+/// Encodes into flac, either with the built-in encoder or with the external
+/// program `flac`.
+pub struct Flac {
+    pub external: bool,
+    pub settings: EncoderSettings,
+    pub tags: TagMap,
+}
+
+impl Flac {
+    fn level(&self) -> u32 {
+        self.settings.level.unwrap_or(8)
+    }
+
+    fn padding(&self) -> usize {
+        self.settings.padding.unwrap_or(8192)
     }
 
-    Ok(())
+    fn native_settings(&self) -> FlacSettings {
+        let mut res = FlacSettings::level(self.level());
+        if let Some(size) = self.settings.block_size {
+            res.block_size = size;
+        }
+        res.padding = self.padding();
+        res
+    }
 }
 
-fn add_metadata(cmd: &mut Command, track: &TrackInfo) {
-    fn add_meta<T>(cmd: &mut Command, name: &str, value: Option<T>)
-    where
-        T: Display,
-    {
-        if let Some(value) = value {
+impl Encoder for Flac {
+    fn extension(&self) -> &'static str {
+        "flac"
+    }
+
+    fn describe(&self) -> String {
+        let mut res = if self.external {
+            format!("flac (external), level {}", self.level())
+        } else {
+            format!("flac (built-in), level {}", self.level())
+        };
+        if !self.external || self.settings.block_size.is_some() {
+            let size = self.native_settings().block_size;
+            res += &format!(", block size {size}");
+        }
+        res += &format!(", padding {}", self.padding());
+        if self.external {
+            res += &encoder::describe_args(&self.settings.args);
+        }
+        res
+    }
+
+    fn encode(
+        &self,
+        src: &Path,
+        dst: &Path,
+        track: &TrackInfo,
+        progress: &dyn Fn(u64),
+    ) -> Result<()> {
+        if !self.external {
+            return flac_enc::encode(
+                src,
+                dst,
+                metadata(track, WavReader::open(src)?.len, &self.tags),
+                &self.native_settings(),
+                progress,
+            );
+        }
+
+        let mut cmd = Command::new("flac");
+        cmd.args(["--silent", "-f", &format!("-{}", self.level())]);
+        if let Some(size) = self.settings.block_size {
+            cmd.args(["-b", &size.to_string()]);
+        }
+        cmd.arg(format!("--padding={}", self.padding()));
+        cmd.args(&self.settings.args);
+        cmd.arg("-o");
+        cmd.arg(dst);
+        for (name, value) in tags::vorbis_comments(track, &self.tags) {
             cmd.args(["-T", &format!("{name}={value}")]);
         }
+        if let Some(cover) = &track.cover {
+            // Front cover with the given mime type.
+            let mut spec = OsString::from(format!("3|{}|||", cover.mime));
+            spec.push(&cover.path);
+            cmd.arg("--picture");
+            cmd.arg(spec);
+        }
+        cmd.arg("-");
+        encoder::run(&mut cmd, src, progress)?;
+
+        // Cuesheet can be given to `flac` only as a file.
+        retag(dst, track, &self.tags)?;
+        Ok(())
+    }
+
+    fn resume(
+        &self,
+        src: &Path,
+        dst: &Path,
+        track: &TrackInfo,
+    ) -> Result<Resume> {
+        let meta = FlacMeta::read(dst)?;
+        if meta.md5() != Some(flac_enc::audio_md5(src)?) {
+            return Ok(Resume::Encode);
+        }
+
+        if meta_match(&meta, track, &self.tags)? {
+            Ok(Resume::Skip)
+        } else {
+            Ok(Resume::Retag)
+        }
     }
 
-    // Fully standard
-    add_meta(cmd, "TITLE", track.title.as_ref());
-    add_meta(cmd, "ARTIST", track.artist.as_ref());
-    add_meta(cmd, "ALBUM", track.album.as_ref());
-    add_meta(cmd, "DATE", track.date);
-    add_meta(cmd, "TRACKNUMBER", track.track);
-    add_meta(cmd, "GENRE", track.genre.as_ref());
-    add_meta(cmd, "ISRC", track.isrc.as_ref());
+    fn retag(&self, dst: &Path, track: &TrackInfo) -> Result<()> {
+        retag(dst, track, &self.tags)?;
+        Ok(())
+    }
 
-    for a in &track.feat {
-        add_meta(cmd, "ARTIST", Some(a));
+    fn can_verify(&self) -> bool {
+        true
     }
 
-    // Standard extensions
-    add_meta(cmd, "DISCNUMBER", track.disc);
-    add_meta(cmd, "VOLUME", track.disc_name.as_ref());
+    fn verify(&self, src: &Path, dst: &Path) -> Result<()> {
+        let format = WavReader::open(src)?.format;
+        let mut flac = FlacReader::open(dst)?;
+        let info = flac.streaminfo();
+        if info.channels != format.channels as u32
+            || info.sample_rate != format.sample_rate
+            || info.bits_per_sample != format.bits as u32
+        {
+            return Err(Error::VerifyFailed);
+        }
+
+        let bytes = format.sample_bytes();
+        let mut md5 = Md5::new();
+        let mut blocks = flac.blocks();
+        let mut buf = vec![];
+        let mut samples = vec![];
+        while let Some(block) = blocks.read_next_or_eof(buf)? {
+            samples.clear();
+            for i in 0..block.duration() {
+                for c in 0..block.channels() {
+                    samples.push(block.sample(c, i));
+                }
+            }
+            flac_enc::update_md5(&mut md5, &samples, bytes);
+            buf = block.into_buffer();
+        }
+
+        let decoded: [u8; 16] = md5.finalize().into();
+        if decoded == flac_enc::audio_md5(src)? {
+            Ok(())
+        } else {
+            Err(Error::VerifyFailed)
+        }
+    }
+}
 
-    // Non standard
-    add_meta(cmd, "ALBUMARTIST", track.album_artist.as_ref());
-    add_meta(cmd, "CDINDEX", track.cdindex.as_ref());
-    if let Some(value) = track.cddb {
-        cmd.args(["-T", &format!("CDDB={value:x}")]);
+/// Replaces the vorbis comments, the front cover and the cuesheet in the
+/// flac file `path` with the metadata from `track`. Comments that are not
+/// produced by cdadd are kept and so is the cover and the cuesheet if they
+/// are not known in `track`. Names of the comments are given by `map`.
+/// Returns `false` if the metadata were already up to date.
+pub fn retag(path: &Path, track: &TrackInfo, map: &TagMap) -> Result<bool> {
+    let mut meta = FlacMeta::read(path)?;
+    if meta_match(&meta, track, map)? {
+        return Ok(false);
     }
+
+    let old = meta.comments().transpose()?.unwrap_or_default();
+    let mut comments: Vec<(&str, String)> = tags::vorbis_comments(track, map);
+    comments.extend(
+        old.iter()
+            .filter(|(n, _)| !tags::is_known(n, map))
+            .map(|(n, v)| (n.as_str(), v.clone())),
+    );
+    meta.set_comments(&comments);
+    if let Some(cover) = &track.cover {
+        meta.set_front_cover(flac_meta::picture(cover));
+    }
+    if let Some(cue) = flac_meta::cuesheet(track, meta.samples()) {
+        meta.set_block(BLOCK_CUESHEET, cue);
+    }
+    meta.write(path)?;
+    Ok(true)
+}
+
+/// Creates the metadata blocks for the given track.
+fn metadata(track: &TrackInfo, samples: u64, map: &TagMap) -> Vec<Block> {
+    let mut res = vec![Block {
+        typ: BLOCK_VORBIS_COMMENT,
+        data: flac_meta::vorbis_comment(&tags::vorbis_comments(track, map)),
+    }];
+    if let Some(cover) = &track.cover {
+        res.push(Block {
+            typ: BLOCK_PICTURE,
+            data: flac_meta::picture(cover),
+        });
+    }
+    if let Some(cue) = flac_meta::cuesheet(track, samples) {
+        res.push(Block {
+            typ: BLOCK_CUESHEET,
+            data: cue,
+        });
+    }
+    res
+}
+
+/// Checks whether the metadata in `meta` produced by cdadd match the
+/// metadata in `track`.
+fn meta_match(
+    meta: &FlacMeta,
+    track: &TrackInfo,
+    map: &TagMap,
+) -> Result<bool> {
+    if let Some(cover) = &track.cover
+        && meta.front_cover() != Some(&flac_meta::picture(cover))
+    {
+        return Ok(false);
+    }
+    if let Some(cue) = flac_meta::cuesheet(track, meta.samples())
+        && meta.block(BLOCK_CUESHEET) != Some(&cue)
+    {
+        return Ok(false);
+    }
+
+    let Some(comments) = meta.comments().transpose()? else {
+        return Ok(false);
+    };
+    let mut old: Vec<_> = comments
+        .into_iter()
+        .filter(|(n, _)| tags::is_known(n, map))
+        .map(|(n, v)| (n.to_ascii_uppercase(), v))
+        .collect();
+    let mut new: Vec<_> = tags::vorbis_comments(track, map)
+        .into_iter()
+        .map(|(n, v)| (n.to_owned(), v))
+        .collect();
+    old.sort();
+    new.sort();
+    Ok(old == new)
 }
@@ -0,0 +1,900 @@
+use std::{
+    borrow::Cow,
+    f64::consts::PI,
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use md5::{Digest, Md5};
+
+use crate::{
+    bit_writer::BitWriter,
+    err::{Error, Result},
+    flac_meta::{self, BLOCK_STREAMINFO, Block},
+    wav::{PcmFormat, WavReader},
+};
+
+/// Settings of the built-in flac encoder.
+pub struct FlacSettings {
+    pub block_size: usize,
+    pub max_lpc_order: usize,
+    pub max_partition_order: u32,
+    pub padding: usize,
+}
+
+impl FlacSettings {
+    /// Settings equivalent to the given compression level of `flac` (0 to
+    /// 8). Level 8 is the same as `flac --best`.
+    pub fn level(level: u32) -> Self {
+        let (block_size, max_lpc_order, max_partition_order) = match level {
+            0..=2 => (1152, 0, 3),
+            3 => (4096, 6, 4),
+            4 => (4096, 8, 4),
+            5 => (4096, 8, 5),
+            6 => (4096, 8, 6),
+            _ => (4096, 12, 6),
+        };
+        Self {
+            block_size,
+            max_lpc_order,
+            max_partition_order,
+            padding: 8192,
+        }
+    }
+}
+
+/// Encodes the wav file `src` into flac file `dst` with the given metadata
+/// blocks. Stream info and padding are added automatically. `progress` is
+/// called with the number of processed bytes of the audio data.
+pub fn encode(
+    src: &Path,
+    dst: &Path,
+    meta: Vec<Block>,
+    settings: &FlacSettings,
+    progress: &dyn Fn(u64),
+) -> Result<()> {
+    let mut wav = WavReader::open(src)?;
+    let format = wav.format;
+    if !(4..=24).contains(&format.bits) {
+        return Err(Error::InvalidWav("Unsupported bits per sample."));
+    }
+
+    let mut out = BufWriter::new(File::create(dst)?);
+    let mut info = StreamInfo::new(format, settings.block_size, wav.len);
+
+    let mut blocks = vec![Block {
+        typ: BLOCK_STREAMINFO,
+        data: info.to_bytes(),
+    }];
+    blocks.extend(meta);
+    if settings.padding != 0 {
+        blocks.extend(flac_meta::padding(settings.padding));
+    }
+    flac_meta::write_blocks(&mut out, &blocks)?;
+
+    let mut enc = FrameEncoder::new(format, settings);
+    let mut md5 = Md5::new();
+    let mut samples = vec![];
+    let mut frame = BitWriter::new();
+    let mut frame_num = 0;
+    let bytes = format.sample_bytes();
+
+    loop {
+        let len = wav.read_samples(&mut samples, settings.block_size)?;
+        if len == 0 {
+            break;
+        }
+
+        update_md5(&mut md5, &samples, bytes);
+
+        frame.clear();
+        enc.encode(&mut frame, frame_num, &samples, len);
+        out.write_all(&frame.bytes)?;
+
+        info.add_frame(frame.bytes.len(), len);
+        frame_num += 1;
+        progress(info.samples * format.block_align() as u64);
+    }
+
+    info.md5 = md5.finalize().into();
+    out.seek(SeekFrom::Start(8))?;
+    out.write_all(&info.to_bytes())?;
+    out.flush()?;
+
+    Ok(())
+}
+
+/// Computes MD5 of the audio data in the wav file the same way as it is
+/// stored in flac stream info.
+pub fn audio_md5(src: &Path) -> Result<[u8; 16]> {
+    let mut wav = WavReader::open(src)?;
+    let bytes = wav.format.sample_bytes();
+    let mut md5 = Md5::new();
+    let mut samples = vec![];
+    while wav.read_samples(&mut samples, 4096)? != 0 {
+        update_md5(&mut md5, &samples, bytes);
+    }
+    Ok(md5.finalize().into())
+}
+
+/// Adds the interleaved samples to MD5 of audio data.
+pub fn update_md5(md5: &mut Md5, samples: &[i32], bytes: usize) {
+    for s in samples {
+        md5.update(&s.to_le_bytes()[..bytes]);
+    }
+}
+
+struct StreamInfo {
+    min_block: usize,
+    max_block: usize,
+    min_frame: usize,
+    max_frame: usize,
+    format: PcmFormat,
+    samples: u64,
+    md5: [u8; 16],
+}
+
+impl StreamInfo {
+    fn new(format: PcmFormat, block_size: usize, len: u64) -> Self {
+        let block = block_size.min(len as usize).max(16);
+        Self {
+            min_block: block,
+            max_block: block,
+            min_frame: usize::MAX,
+            max_frame: 0,
+            format,
+            samples: 0,
+            md5: [0; 16],
+        }
+    }
+
+    fn add_frame(&mut self, size: usize, samples: usize) {
+        self.min_frame = self.min_frame.min(size);
+        self.max_frame = self.max_frame.max(size);
+        self.samples += samples as u64;
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut w = BitWriter::new();
+        w.write(16, self.min_block as u64);
+        w.write(16, self.max_block as u64);
+        // Zero means unknown.
+        w.write(
+            24,
+            if self.max_frame == 0 {
+                0
+            } else {
+                self.min_frame as u64
+            },
+        );
+        w.write(24, self.max_frame as u64);
+        w.write(20, self.format.sample_rate as u64);
+        w.write(3, self.format.channels as u64 - 1);
+        w.write(5, self.format.bits as u64 - 1);
+        w.write(4, self.samples >> 32);
+        w.write(32, self.samples);
+        w.bytes.extend(self.md5);
+        w.bytes
+    }
+}
+
+/// Encodes blocks of samples into flac frames.
+struct FrameEncoder<'a> {
+    format: PcmFormat,
+    settings: &'a FlacSettings,
+    channels: Vec<Vec<i64>>,
+    window: Vec<f64>,
+}
+
+enum Predictor {
+    Constant,
+    Verbatim,
+    Fixed(usize),
+    Lpc {
+        coefs: Vec<i64>,
+        precision: u32,
+        shift: u32,
+    },
+}
+
+struct Rice {
+    param_bits: u32,
+    order: u32,
+    params: Vec<u32>,
+}
+
+struct Subframe {
+    pred: Predictor,
+    /// All the samples for constant and verbatim subframes. Otherwise the
+    /// warmup samples followed by the residual.
+    residual: Vec<i64>,
+    rice: Rice,
+    bits: usize,
+}
+
+impl<'a> FrameEncoder<'a> {
+    fn new(format: PcmFormat, settings: &'a FlacSettings) -> Self {
+        Self {
+            format,
+            settings,
+            channels: vec![vec![]; format.channels as usize],
+            window: tukey(settings.block_size, 0.5),
+        }
+    }
+
+    fn encode(
+        &mut self,
+        w: &mut BitWriter,
+        num: u64,
+        samples: &[i32],
+        len: usize,
+    ) {
+        let chan_cnt = self.format.channels as usize;
+        for (i, c) in self.channels.iter_mut().enumerate() {
+            c.clear();
+            c.extend(
+                samples.iter().skip(i).step_by(chan_cnt).map(|s| *s as i64),
+            );
+        }
+
+        let bps = self.format.bits as u32;
+        let (assignment, subframes) = if chan_cnt == 2 {
+            self.plan_stereo(bps)
+        } else {
+            let subframes = self
+                .channels
+                .iter()
+                .map(|c| (self.plan_subframe(c, bps), bps))
+                .collect();
+            (chan_cnt as u64 - 1, subframes)
+        };
+
+        self.write_header(w, num, len, assignment);
+        for (s, b) in subframes {
+            write_subframe(w, &s, b);
+        }
+        w.align();
+        let crc = crc16(&w.bytes);
+        w.write(16, crc as u64);
+    }
+
+    fn plan_stereo(&self, bps: u32) -> (u64, Vec<(Subframe, u32)>) {
+        let l = &self.channels[0];
+        let r = &self.channels[1];
+        let mid: Vec<_> = l.iter().zip(r).map(|(l, r)| (l + r) >> 1).collect();
+        let side: Vec<_> = l.iter().zip(r).map(|(l, r)| l - r).collect();
+
+        let l = self.plan_subframe(l, bps);
+        let r = self.plan_subframe(r, bps);
+        let m = self.plan_subframe(&mid, bps);
+        let s = self.plan_subframe(&side, bps + 1);
+
+        let modes = [
+            (1, l.bits + r.bits),
+            (8, l.bits + s.bits),
+            (9, s.bits + r.bits),
+            (10, m.bits + s.bits),
+        ];
+        let (mode, _) = modes.into_iter().min_by_key(|(_, b)| *b).unwrap();
+
+        let subframes = match mode {
+            1 => vec![(l, bps), (r, bps)],
+            8 => vec![(l, bps), (s, bps + 1)],
+            9 => vec![(s, bps + 1), (r, bps)],
+            _ => vec![(m, bps), (s, bps + 1)],
+        };
+        (mode, subframes)
+    }
+
+    fn write_header(
+        &self,
+        w: &mut BitWriter,
+        num: u64,
+        len: usize,
+        assignment: u64,
+    ) {
+        w.write(14, 0x3FFE);
+        w.write(1, 0);
+        // Fixed block size.
+        w.write(1, 0);
+
+        let size_code = match len {
+            _ if len != self.settings.block_size => None,
+            192 => Some(1),
+            576 => Some(2),
+            1152 => Some(3),
+            2304 => Some(4),
+            4608 => Some(5),
+            256 => Some(8),
+            512 => Some(9),
+            1024 => Some(10),
+            2048 => Some(11),
+            4096 => Some(12),
+            8192 => Some(13),
+            16384 => Some(14),
+            32768 => Some(15),
+            _ => None,
+        };
+        let size_code = size_code.unwrap_or(if len <= 256 { 6 } else { 7 });
+        w.write(4, size_code);
+
+        w.write(
+            4,
+            match self.format.sample_rate {
+                88200 => 1,
+                176400 => 2,
+                192000 => 3,
+                8000 => 4,
+                16000 => 5,
+                22050 => 6,
+                24000 => 7,
+                32000 => 8,
+                44100 => 9,
+                48000 => 10,
+                96000 => 11,
+                _ => 0,
+            },
+        );
+        w.write(4, assignment);
+        w.write(
+            3,
+            match self.format.bits {
+                8 => 1,
+                12 => 2,
+                16 => 4,
+                20 => 5,
+                24 => 6,
+                _ => 0,
+            },
+        );
+        w.write(1, 0);
+
+        write_utf8(w, num);
+        match size_code {
+            6 => w.write(8, len as u64 - 1),
+            7 => w.write(16, len as u64 - 1),
+            _ => {}
+        }
+
+        let crc = crc8(&w.bytes);
+        w.write(8, crc as u64);
+    }
+
+    fn plan_subframe(&self, x: &[i64], bps: u32) -> Subframe {
+        if x.iter().all(|s| *s == x[0]) {
+            return Subframe {
+                pred: Predictor::Constant,
+                residual: vec![x[0]],
+                rice: Rice::empty(),
+                bits: 8 + bps as usize,
+            };
+        }
+
+        let mut best = Subframe {
+            pred: Predictor::Verbatim,
+            residual: x.to_vec(),
+            rice: Rice::empty(),
+            bits: 8 + bps as usize * x.len(),
+        };
+
+        for order in 0..=4.min(x.len() - 1) {
+            let residual = fixed_residual(x, order);
+            let Some((rice, bits)) = self.plan_rice(&residual[order..], order)
+            else {
+                continue;
+            };
+            let bits = bits + 8 + order * bps as usize;
+            if bits < best.bits {
+                best = Subframe {
+                    pred: Predictor::Fixed(order),
+                    residual,
+                    rice,
+                    bits,
+                };
+            }
+        }
+
+        if let Some(lpc) = self.plan_lpc(x, bps)
+            && lpc.bits < best.bits
+        {
+            best = lpc;
+        }
+
+        best
+    }
+
+    fn plan_lpc(&self, x: &[i64], bps: u32) -> Option<Subframe> {
+        let max_order = self.settings.max_lpc_order.min(x.len() - 1);
+        if max_order == 0 {
+            return None;
+        }
+
+        let window = self.window(x.len());
+        let data: Vec<_> = x
+            .iter()
+            .zip(window.iter())
+            .map(|(x, w)| *x as f64 * w)
+            .collect();
+        let autoc: Vec<_> = (0..=max_order)
+            .map(|lag| data[lag..].iter().zip(&data).map(|(a, b)| a * b).sum())
+            .collect();
+        let (coefs, errors) = levinson(&autoc, max_order);
+        if coefs.is_empty() {
+            return None;
+        }
+
+        let precision = qlp_precision(bps, x.len());
+        let order = best_order(&errors, x.len(), bps + precision);
+        let (qcoefs, shift) = quantize(&coefs[order - 1], precision)?;
+
+        let residual = lpc_residual(x, &qcoefs, shift)?;
+        let (rice, bits) = self.plan_rice(&residual[order..], order)?;
+        let bits = bits
+            + 8
+            + order * bps as usize
+            + 4
+            + 5
+            + order * precision as usize;
+
+        Some(Subframe {
+            pred: Predictor::Lpc {
+                coefs: qcoefs,
+                precision,
+                shift,
+            },
+            residual,
+            rice,
+            bits,
+        })
+    }
+
+    /// Chooses the best partition order and rice parameters for the
+    /// residual `res` of predictor with the given order. Returns the
+    /// estimated size of the residual in bits.
+    fn plan_rice(&self, res: &[i64], order: usize) -> Option<(Rice, usize)> {
+        if res
+            .iter()
+            .any(|r| *r > i32::MAX as i64 || *r < i32::MIN as i64)
+        {
+            return None;
+        }
+
+        let len = res.len() + order;
+        let mut best: Option<(Rice, usize)> = None;
+        for porder in 0..=self.settings.max_partition_order {
+            let part = len >> porder;
+            if !len.is_multiple_of(1 << porder) || part <= order {
+                break;
+            }
+
+            let mut params = vec![];
+            let mut bits = 2 + 4;
+            let mut start = 0;
+            for p in 0..1 << porder {
+                let cnt = if p == 0 { part - order } else { part };
+                let sum: u64 =
+                    res[start..start + cnt].iter().map(|r| fold(*r)).sum();
+                start += cnt;
+
+                let (k, b) = (0..=30)
+                    .map(|k| (k, cnt * (k as usize + 1) + (sum >> k) as usize))
+                    .min_by_key(|(_, b)| *b)
+                    .unwrap();
+                params.push(k);
+                bits += b;
+            }
+
+            let param_bits =
+                if params.iter().any(|k| *k > 14) { 5 } else { 4 };
+            bits += params.len() * param_bits as usize;
+
+            if best.as_ref().is_none_or(|(_, b)| bits < *b) {
+                best = Some((
+                    Rice {
+                        param_bits,
+                        order: porder,
+                        params,
+                    },
+                    bits,
+                ));
+            }
+        }
+
+        best
+    }
+
+    fn window(&self, len: usize) -> Cow<'_, [f64]> {
+        if self.window.len() == len {
+            self.window.as_slice().into()
+        } else {
+            tukey(len, 0.5).into()
+        }
+    }
+}
+
+impl Rice {
+    fn empty() -> Self {
+        Self {
+            param_bits: 4,
+            order: 0,
+            params: vec![],
+        }
+    }
+}
+
+fn write_subframe(w: &mut BitWriter, s: &Subframe, bps: u32) {
+    w.write(1, 0);
+    match &s.pred {
+        Predictor::Constant => w.write(6, 0),
+        Predictor::Verbatim => w.write(6, 1),
+        Predictor::Fixed(o) => w.write(6, 8 | *o as u64),
+        Predictor::Lpc { coefs, .. } => {
+            w.write(6, 32 | (coefs.len() as u64 - 1))
+        }
+    }
+    // No wasted bits.
+    w.write(1, 0);
+
+    let x = &s.residual;
+    match &s.pred {
+        Predictor::Constant => {
+            w.write_signed(bps, x[0]);
+            return;
+        }
+        Predictor::Verbatim => {
+            for s in x {
+                w.write_signed(bps, *s);
+            }
+            return;
+        }
+        _ => {}
+    }
+    // Warmup samples are stored before the residual.
+    let order = match &s.pred {
+        Predictor::Fixed(o) => *o,
+        Predictor::Lpc { coefs, .. } => coefs.len(),
+        _ => unreachable!(),
+    };
+    for s in &x[..order] {
+        w.write_signed(bps, *s);
+    }
+    if let Predictor::Lpc {
+        coefs,
+        precision,
+        shift,
+    } = &s.pred
+    {
+        w.write(4, *precision as u64 - 1);
+        w.write(5, *shift as u64);
+        for c in coefs {
+            w.write_signed(*precision, *c);
+        }
+    }
+
+    let rice = &s.rice;
+    w.write(2, if rice.param_bits == 5 { 1 } else { 0 });
+    w.write(4, rice.order as u64);
+    let mut res = x[order..].iter();
+    let part = (x.len()) >> rice.order;
+    for (i, k) in rice.params.iter().enumerate() {
+        let cnt = if i == 0 { part - order } else { part };
+        w.write(rice.param_bits, *k as u64);
+        for r in res.by_ref().take(cnt) {
+            let u = fold(*r);
+            w.write_unary(u >> k);
+            w.write(*k, u);
+        }
+    }
+}
+
+/// Returns the warmup samples followed by the residual of the fixed
+/// predictor with the given order.
+fn fixed_residual(x: &[i64], order: usize) -> Vec<i64> {
+    let mut res = x[..order].to_vec();
+    res.extend((order..x.len()).map(|i| match order {
+        0 => x[i],
+        1 => x[i] - x[i - 1],
+        2 => x[i] - 2 * x[i - 1] + x[i - 2],
+        3 => x[i] - 3 * x[i - 1] + 3 * x[i - 2] - x[i - 3],
+        _ => x[i] - 4 * x[i - 1] + 6 * x[i - 2] - 4 * x[i - 3] + x[i - 4],
+    }));
+    res
+}
+
+/// Same as [`fixed_residual`], but with the lpc predictor. Returns [`None`]
+/// if the prediction would overflow.
+fn lpc_residual(x: &[i64], coefs: &[i64], shift: u32) -> Option<Vec<i64>> {
+    let order = coefs.len();
+    let mut res = x[..order].to_vec();
+    for i in order..x.len() {
+        let pred: i64 = coefs
+            .iter()
+            .zip(x[i - order..i].iter().rev())
+            .map(|(c, x)| c * x)
+            .sum();
+        let pred = pred >> shift;
+        if pred > i32::MAX as i64 || pred < i32::MIN as i64 {
+            return None;
+        }
+        res.push(x[i] - pred);
+    }
+    Some(res)
+}
+
+/// Computes the lpc coefficients for all orders up to `max_order` from the
+/// autocorrelation. Returns the coefficients and the prediction error for
+/// each order.
+fn levinson(autoc: &[f64], max_order: usize) -> (Vec<Vec<f64>>, Vec<f64>) {
+    let mut lpc = vec![0.; max_order];
+    let mut coefs = vec![];
+    let mut errors = vec![];
+    let mut err = autoc[0];
+    if err <= 0. {
+        return (coefs, errors);
+    }
+
+    for i in 0..max_order {
+        let mut r = -autoc[i + 1];
+        for j in 0..i {
+            r -= lpc[j] * autoc[i - j];
+        }
+        r /= err;
+
+        lpc[i] = r;
+        for j in 0..i / 2 {
+            let tmp = lpc[j];
+            lpc[j] += r * lpc[i - 1 - j];
+            lpc[i - 1 - j] += r * tmp;
+        }
+        if i & 1 == 1 {
+            lpc[i / 2] += lpc[i / 2] * r;
+        }
+        err *= 1. - r * r;
+
+        coefs.push(lpc[..=i].iter().map(|c| -c).collect());
+        errors.push(err);
+        if err <= 0. {
+            break;
+        }
+    }
+
+    (coefs, errors)
+}
+
+/// Estimates the order with the smallest output.
+fn best_order(errors: &[f64], len: usize, bits_per_order: u32) -> usize {
+    let scale = 0.5 / len as f64;
+    let mut best = (1, f64::MAX);
+    for (i, err) in errors.iter().enumerate() {
+        let order = i + 1;
+        let bps = if *err > 0. {
+            (0.5 * (scale * err).log2()).max(0.)
+        } else {
+            0.
+        };
+        let bits = bps * (len - order) as f64
+            + (order * bits_per_order as usize) as f64;
+        if bits < best.1 {
+            best = (order, bits);
+        }
+    }
+    best.0
+}
+
+/// Precision of the quantized lpc coefficients (same as in libFLAC).
+fn qlp_precision(bps: u32, len: usize) -> u32 {
+    if bps < 16 {
+        return (2 + bps / 2).max(5);
+    } else if bps > 16 {
+        return 15;
+    }
+
+    match len {
+        0..=192 => 7,
+        193..=384 => 8,
+        385..=576 => 9,
+        577..=1152 => 10,
+        1153..=2304 => 11,
+        2305..=4608 => 12,
+        _ => 13,
+    }
+}
+
+fn quantize(coefs: &[f64], precision: u32) -> Option<(Vec<i64>, u32)> {
+    let cmax = coefs.iter().map(|c| c.abs()).fold(0., f64::max);
+    if cmax <= 0. || !cmax.is_finite() {
+        return None;
+    }
+
+    let qmax = (1i64 << (precision - 1)) - 1;
+    let qmin = -qmax - 1;
+    let shift = precision as i32 - 2 - cmax.log2().floor() as i32;
+    if shift < 0 {
+        return None;
+    }
+    let shift = shift.min(15) as u32;
+
+    let mut err = 0.;
+    let coefs = coefs
+        .iter()
+        .map(|c| {
+            err += c * (1 << shift) as f64;
+            let q = (err.round() as i64).clamp(qmin, qmax);
+            err -= q as f64;
+            q
+        })
+        .collect();
+
+    Some((coefs, shift))
+}
+
+fn tukey(len: usize, p: f64) -> Vec<f64> {
+    let mut res = vec![1.; len];
+    let np = (p / 2. * len as f64) as usize;
+    if np > 1 {
+        let np = np - 1;
+        for n in 0..=np {
+            let w = 0.5 - 0.5 * (PI * n as f64 / np as f64).cos();
+            res[n] = w;
+            res[len - 1 - n] = w;
+        }
+    }
+    res
+}
+
+fn fold(r: i64) -> u64 {
+    ((r << 1) ^ (r >> 63)) as u64
+}
+
+fn write_utf8(w: &mut BitWriter, v: u64) {
+    if v < 0x80 {
+        w.write(8, v);
+        return;
+    }
+
+    let cont = match v {
+        0x80..0x800 => 1,
+        0x800..0x10000 => 2,
+        0x10000..0x200000 => 3,
+        0x200000..0x4000000 => 4,
+        0x4000000..0x80000000 => 5,
+        _ => 6,
+    };
+    let prefix = (0xFF00u64 >> (cont + 1)) & 0xFF;
+    w.write(8, prefix | (v >> (cont * 6)));
+    for i in (0..cont).rev() {
+        w.write(8, 0x80 | ((v >> (i * 6)) & 0x3F));
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0, |mut crc, b| {
+        crc ^= b;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |mut crc, b| {
+        crc ^= (*b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use claxon::FlacReader;
+
+    use super::*;
+    use crate::{image::TempDir, wav::WavWriter};
+
+    /// Generates sine with noise, silence and full scale peaks, so that all
+    /// kinds of subframes are used.
+    fn samples(format: PcmFormat, len: usize) -> Vec<i32> {
+        let max = (1 << (format.bits - 1)) - 1;
+        let min = -max - 1;
+        let mut rand: u32 = 1;
+        let mut res = vec![];
+        for i in 0..len {
+            for c in 0..format.channels as usize {
+                rand = rand.wrapping_mul(1664525).wrapping_add(1013904223);
+                let noise = (rand >> 16) as i32 % (max / 16 + 1);
+                let t = i as f64 / format.sample_rate as f64;
+                let sine = (t * 440. * (c + 1) as f64 * 2. * PI).sin();
+                let s = match i {
+                    1000..2000 => 0,
+                    3000..3010 => max,
+                    3010..3020 => min,
+                    _ => (sine * max as f64 / 2.) as i32 + noise,
+                };
+                res.push(s.clamp(min, max));
+            }
+        }
+        res
+    }
+
+    fn round_trip(bits: u16, channels: u16, level: u32) {
+        let format = PcmFormat {
+            channels,
+            sample_rate: 44100,
+            bits,
+        };
+        let dir = TempDir::new().unwrap();
+        let wav = dir.path().join("a.wav");
+        let flac = dir.path().join("a.flac");
+        // Length is not multiple of the block size.
+        let samples = samples(format, 10000);
+        let mut writer = WavWriter::create(&wav, format).unwrap();
+        writer.write_samples(&samples).unwrap();
+        writer.finish().unwrap();
+
+        let settings = FlacSettings::level(level);
+        encode(&wav, &flac, vec![], &settings, &|_| {}).unwrap();
+
+        let mut reader = FlacReader::open(&flac).unwrap();
+        let info = reader.streaminfo();
+        assert_eq!(info.bits_per_sample, bits as u32);
+        assert_eq!(info.channels, channels as u32);
+        assert_eq!(info.samples, Some(10000));
+        assert_eq!(info.md5sum, audio_md5(&wav).unwrap());
+        let decoded: Vec<_> =
+            reader.samples().collect::<claxon::Result<_>>().unwrap();
+        assert_eq!(decoded, samples);
+    }
+
+    #[test]
+    fn round_trip_formats() {
+        for bits in [8, 16, 24] {
+            for channels in [1, 2, 6] {
+                for level in [0, 5, 8] {
+                    round_trip(bits, channels, level);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn crc() {
+        // Check values of CRC-8 and CRC-16/UMTS.
+        assert_eq!(crc8(b"123456789"), 0xF4);
+        assert_eq!(crc16(b"123456789"), 0xFEE8);
+        assert_eq!(crc8(&[]), 0);
+        assert_eq!(crc16(&[]), 0);
+    }
+
+    fn utf8(v: u64) -> Vec<u8> {
+        let mut w = BitWriter::new();
+        write_utf8(&mut w, v);
+        w.bytes
+    }
+
+    #[test]
+    fn utf8_numbers() {
+        for v in [0, 0x7F, 0x80, 0x7FF, 0x800, 0xFFFF, 0x10000, 0x10FFFF] {
+            let c = char::from_u32(v as u32).unwrap();
+            assert_eq!(utf8(v), c.to_string().as_bytes(), "{v:x}");
+        }
+        assert_eq!(utf8(0x3FFFFFF), [0xFB, 0xBF, 0xBF, 0xBF, 0xBF]);
+        assert_eq!(utf8(0x4000000), [0xFC, 0x84, 0x80, 0x80, 0x80, 0x80]);
+        assert_eq!(utf8(0x7FFFFFFF), [0xFD, 0xBF, 0xBF, 0xBF, 0xBF, 0xBF]);
+        assert_eq!(
+            utf8(0xFFFFFFFFF),
+            [0xFE, 0xBF, 0xBF, 0xBF, 0xBF, 0xBF, 0xBF]
+        );
+    }
+}
@@ -0,0 +1,428 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+};
+
+use crate::{
+    cover::Cover,
+    err::{Error, Result},
+    track_info::TrackInfo,
+};
+
+pub const BLOCK_STREAMINFO: u8 = 0;
+pub const BLOCK_PADDING: u8 = 1;
+pub const BLOCK_VORBIS_COMMENT: u8 = 4;
+pub const BLOCK_CUESHEET: u8 = 5;
+pub const BLOCK_PICTURE: u8 = 6;
+
+/// Picture type of front cover.
+const PICTURE_FRONT_COVER: u32 = 3;
+
+/// Number of samples in single CD sector.
+const SECTOR_SAMPLES: u64 = 588;
+/// Track number of lead-out on CD.
+const LEAD_OUT: u8 = 170;
+
+/// Maximum size of data of single metadata block. The size is stored in 24
+/// bits.
+pub const MAX_BLOCK_SIZE: usize = (1 << 24) - 1;
+
+pub const VENDOR: &str = concat!("cdadd ", env!("CARGO_PKG_VERSION"));
+
+/// Padding added when the metadata no longer fit and the file has to be
+/// rewritten.
+const DEFAULT_PADDING: usize = 8192;
+
+/// Metadata block of flac file.
+pub struct Block {
+    pub typ: u8,
+    pub data: Vec<u8>,
+}
+
+/// Metadata of existing flac file.
+pub struct FlacMeta {
+    pub blocks: Vec<Block>,
+    /// Offset of the first audio frame in the file.
+    audio_start: u64,
+}
+
+impl FlacMeta {
+    /// Reads the metadata blocks from flac file.
+    pub fn read<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut file = BufReader::new(File::open(path)?);
+
+        let mut marker = [0; 4];
+        file.read_exact(&mut marker)?;
+        if &marker != b"fLaC" {
+            return Err(Error::InvalidFlac("Missing flac marker."));
+        }
+
+        let mut blocks = vec![];
+        let mut audio_start = 4;
+        loop {
+            let mut head = [0; 4];
+            file.read_exact(&mut head)?;
+            let len = u32::from_be_bytes([0, head[1], head[2], head[3]]);
+            let mut data = vec![0; len as usize];
+            file.read_exact(&mut data)?;
+            audio_start += 4 + len as u64;
+
+            blocks.push(Block {
+                typ: head[0] & 0x7F,
+                data,
+            });
+            if head[0] & 0x80 != 0 {
+                break;
+            }
+        }
+
+        if blocks.first().is_none_or(|b| b.typ != BLOCK_STREAMINFO) {
+            return Err(Error::InvalidFlac("Missing stream info."));
+        }
+
+        Ok(Self {
+            blocks,
+            audio_start,
+        })
+    }
+
+    /// Gets MD5 of the audio data from stream info. Returns [`None`] if the
+    /// MD5 is not set.
+    pub fn md5(&self) -> Option<[u8; 16]> {
+        let info = &self.blocks[0].data;
+        let md5: [u8; 16] = info.get(18..34)?.try_into().ok()?;
+        (md5 != [0; 16]).then_some(md5)
+    }
+
+    /// Gets the total number of samples per channel from stream info.
+    pub fn samples(&self) -> u64 {
+        let info = &self.blocks[0].data;
+        info.get(13..18).map_or(0, |s| {
+            u64::from_be_bytes([0, 0, 0, s[0] & 0xF, s[1], s[2], s[3], s[4]])
+        })
+    }
+
+    /// Gets the data of the first block of the given type.
+    pub fn block(&self, typ: u8) -> Option<&[u8]> {
+        self.blocks
+            .iter()
+            .find(|b| b.typ == typ)
+            .map(|b| b.data.as_slice())
+    }
+
+    /// Replaces the first block of the given type or adds it after the
+    /// stream info.
+    pub fn set_block(&mut self, typ: u8, data: Vec<u8>) {
+        if let Some(b) = self.blocks.iter_mut().find(|b| b.typ == typ) {
+            b.data = data;
+        } else {
+            self.blocks.insert(1, Block { typ, data });
+        }
+    }
+
+    /// Gets the vorbis comments. Returns [`None`] if there are no vorbis
+    /// comments.
+    pub fn comments(&self) -> Option<Result<Vec<(String, String)>>> {
+        self.blocks
+            .iter()
+            .find(|b| b.typ == BLOCK_VORBIS_COMMENT)
+            .map(|b| parse_vorbis_comment(&b.data))
+    }
+
+    /// Replaces the vorbis comments.
+    pub fn set_comments(&mut self, comments: &[(&str, String)]) {
+        self.set_block(BLOCK_VORBIS_COMMENT, vorbis_comment(comments));
+    }
+
+    /// Gets the data of the picture block with front cover.
+    pub fn front_cover(&self) -> Option<&[u8]> {
+        self.blocks
+            .iter()
+            .find(|b| b.typ == BLOCK_PICTURE && is_front_cover(&b.data))
+            .map(|b| b.data.as_slice())
+    }
+
+    /// Replaces the front cover picture block. Other pictures are kept.
+    pub fn set_front_cover(&mut self, data: Vec<u8>) {
+        self.blocks
+            .retain(|b| b.typ != BLOCK_PICTURE || !is_front_cover(&b.data));
+        let pos = self
+            .blocks
+            .iter()
+            .position(|b| b.typ == BLOCK_VORBIS_COMMENT)
+            .unwrap_or(0)
+            + 1;
+        self.blocks.insert(
+            pos,
+            Block {
+                typ: BLOCK_PICTURE,
+                data,
+            },
+        );
+    }
+
+    /// Writes the metadata to the flac file that it was read from. If the
+    /// metadata fit into the original space (including the padding), the
+    /// file is modified in place. Otherwise the whole file is rewritten.
+    pub fn write<P>(&mut self, path: P) -> Result<()>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        self.blocks.retain(|b| b.typ != BLOCK_PADDING);
+        let size: u64 = 4 + self
+            .blocks
+            .iter()
+            .map(|b| 4 + b.data.len() as u64)
+            .sum::<u64>();
+
+        // Padding block needs at least space for its header.
+        if size == self.audio_start || size + 4 <= self.audio_start {
+            if size != self.audio_start {
+                let size = (self.audio_start - size - 4) as usize;
+                self.blocks.extend(padding(size));
+            }
+            let mut file = OpenOptions::new().write(true).open(path)?;
+            let mut out = BufWriter::new(&mut file);
+            write_blocks(&mut out, &self.blocks)?;
+            out.flush()?;
+            return Ok(());
+        }
+
+        self.blocks.extend(padding(DEFAULT_PADDING));
+        let tmp = path.with_extension("cdadd-tmp");
+        let res = (|| -> Result<()> {
+            let mut src = File::open(path)?;
+            src.seek(SeekFrom::Start(self.audio_start))?;
+            let mut out = BufWriter::new(File::create(&tmp)?);
+            write_blocks(&mut out, &self.blocks)?;
+            io::copy(&mut src, &mut out)?;
+            out.flush()?;
+            Ok(())
+        })();
+
+        match res {
+            Ok(_) => {
+                fs::rename(&tmp, path)?;
+                self.audio_start = size + 4 + DEFAULT_PADDING as u64;
+                Ok(())
+            }
+            Err(e) => {
+                _ = fs::remove_file(&tmp);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Creates padding blocks with `size` bytes of data (without the header of
+/// the first block). Padding that doesn't fit into single block is split
+/// into multiple blocks.
+pub fn padding(mut size: usize) -> Vec<Block> {
+    let mut res = vec![];
+    while size > MAX_BLOCK_SIZE {
+        // The rest must have space for header of the next block.
+        let len = if size - MAX_BLOCK_SIZE >= 4 {
+            MAX_BLOCK_SIZE
+        } else {
+            MAX_BLOCK_SIZE - 4
+        };
+        res.push(Block {
+            typ: BLOCK_PADDING,
+            data: vec![0; len],
+        });
+        size -= len + 4;
+    }
+    res.push(Block {
+        typ: BLOCK_PADDING,
+        data: vec![0; size],
+    });
+    res
+}
+
+/// Writes the flac marker and the metadata blocks.
+pub fn write_blocks<W>(out: &mut W, blocks: &[Block]) -> Result<()>
+where
+    W: Write,
+{
+    out.write_all(b"fLaC")?;
+    if blocks.iter().any(|b| b.data.len() > MAX_BLOCK_SIZE) {
+        return Err(Error::InvalidFlac("Metadata block is too large."));
+    }
+    for (i, b) in blocks.iter().enumerate() {
+        let last = i + 1 == blocks.len();
+        let len = (b.data.len() as u32).to_be_bytes();
+        out.write_all(&[b.typ | ((last as u8) << 7), len[1], len[2], len[3]])?;
+        out.write_all(&b.data)?;
+    }
+    Ok(())
+}
+
+/// Creates data of vorbis comment block.
+pub fn vorbis_comment(comments: &[(&str, String)]) -> Vec<u8> {
+    let mut res = vec![];
+    res.extend((VENDOR.len() as u32).to_le_bytes());
+    res.extend(VENDOR.as_bytes());
+    res.extend((comments.len() as u32).to_le_bytes());
+    for (name, value) in comments {
+        res.extend(((name.len() + value.len() + 1) as u32).to_le_bytes());
+        res.extend(name.as_bytes());
+        res.push(b'=');
+        res.extend(value.as_bytes());
+    }
+    res
+}
+
+/// Creates data of picture block with the front cover.
+pub fn picture(cover: &Cover) -> Vec<u8> {
+    let mut res = vec![];
+    res.extend(PICTURE_FRONT_COVER.to_be_bytes());
+    res.extend((cover.mime.len() as u32).to_be_bytes());
+    res.extend(cover.mime.as_bytes());
+    // Empty description.
+    res.extend(0_u32.to_be_bytes());
+    res.extend(cover.width.to_be_bytes());
+    res.extend(cover.height.to_be_bytes());
+    res.extend(cover.depth.to_be_bytes());
+    // Number of colors is used only for indexed images.
+    res.extend(0_u32.to_be_bytes());
+    res.extend((cover.data.len() as u32).to_be_bytes());
+    res.extend(&cover.data);
+    res
+}
+
+/// Creates data of cuesheet block with the indices of the track. `samples`
+/// is the length of the track. The position of the track on the disc is
+/// stored in the `CDTOC` tag. Returns [`None`] if the position of the track
+/// is not known.
+pub fn cuesheet(track: &TrackInfo, samples: u64) -> Option<Vec<u8>> {
+    track.start?;
+    let is_cd = samples.is_multiple_of(SECTOR_SAMPLES);
+
+    let mut res = vec![0; 128];
+    if let Some(barcode) = &track.barcode {
+        let len = barcode.len().min(128);
+        res[..len].copy_from_slice(&barcode.as_bytes()[..len]);
+    }
+    // Lead-in of CD is 2 seconds. It must be 0 for other audio.
+    let lead_in = if is_cd { 150 * SECTOR_SAMPLES } else { 0 };
+    res.extend(lead_in.to_be_bytes());
+    res.push((is_cd as u8) << 7);
+    res.extend([0; 258]);
+    res.push(2);
+
+    // The track
+    let number = track.track.unwrap_or(1).min(99) as u8;
+    res.extend(0_u64.to_be_bytes());
+    res.push(number);
+    let mut isrc = [0; 12];
+    if let Some(i) = &track.isrc {
+        let len = i.len().min(12);
+        isrc[..len].copy_from_slice(&i.as_bytes()[..len]);
+    }
+    res.extend(isrc);
+    res.push((track.pre_emphasis as u8) << 6);
+    res.extend([0; 13]);
+
+    let mut indices = vec![(1, 0)];
+    indices.extend(
+        track
+            .indices
+            .iter()
+            .map(|(n, i)| (*n, *i as u64 * SECTOR_SAMPLES))
+            .filter(|(n, i)| *n > 1 && *n < 100 && *i != 0 && *i < samples),
+    );
+    res.push(indices.len() as u8);
+    for (n, offset) in indices {
+        res.extend(offset.to_be_bytes());
+        res.push(n as u8);
+        res.extend([0; 3]);
+    }
+
+    // Lead-out
+    res.extend(samples.to_be_bytes());
+    res.push(LEAD_OUT);
+    res.extend([0; 12]);
+    res.extend([0; 14]);
+    res.push(0);
+
+    Some(res)
+}
+
+fn is_front_cover(data: &[u8]) -> bool {
+    data.starts_with(&PICTURE_FRONT_COVER.to_be_bytes())
+}
+
+fn parse_vorbis_comment(mut data: &[u8]) -> Result<Vec<(String, String)>> {
+    fn read_str<'a>(data: &mut &'a [u8]) -> Result<&'a [u8]> {
+        let len = read_u32(data)? as usize;
+        if data.len() < len {
+            return Err(Error::InvalidFlac("Vorbis comment is too short."));
+        }
+        let (s, rest) = data.split_at(len);
+        *data = rest;
+        Ok(s)
+    }
+
+    fn read_u32(data: &mut &[u8]) -> Result<u32> {
+        let Some((n, rest)) = data.split_first_chunk() else {
+            return Err(Error::InvalidFlac("Vorbis comment is too short."));
+        };
+        *data = rest;
+        Ok(u32::from_le_bytes(*n))
+    }
+
+    // Vendor
+    read_str(&mut data)?;
+    let cnt = read_u32(&mut data)?;
+    let mut res = vec![];
+    for _ in 0..cnt {
+        let c = String::from_utf8_lossy(read_str(&mut data)?);
+        let Some((name, value)) = c.split_once('=') else {
+            return Err(Error::InvalidFlac("Invalid vorbis comment."));
+        };
+        res.push((name.to_owned(), value.to_owned()));
+    }
+
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cuesheet_indices() {
+        let track = TrackInfo {
+            track: Some(3),
+            start: Some(1000),
+            indices: vec![(3, 500)],
+            ..Default::default()
+        };
+        let samples = 1000 * SECTOR_SAMPLES;
+        let cue = cuesheet(&track, samples).unwrap();
+        // Lead-in, CD flag and number of tracks
+        assert_eq!(cue[128..136], 88200_u64.to_be_bytes());
+        assert_eq!(cue[136], 0x80);
+        assert_eq!(cue[395], 2);
+
+        let cue_track = &cue[396..];
+        assert_eq!(cue_track[..8], 0_u64.to_be_bytes());
+        assert_eq!(cue_track[8], 3);
+        assert_eq!(cue_track[35], 2);
+        let indices = &cue_track[36..];
+        assert_eq!(indices[..9], [0, 0, 0, 0, 0, 0, 0, 0, 1]);
+        let offset = (500 * SECTOR_SAMPLES).to_be_bytes();
+        assert_eq!(indices[12..20], offset);
+        assert_eq!(indices[20], 3);
+
+        // Lead-in of other than CD audio must be 0.
+        let cue = cuesheet(&track, samples + 1).unwrap();
+        assert_eq!(cue[128..136], [0; 8]);
+        assert_eq!(cue[136], 0);
+    }
+}
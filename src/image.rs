@@ -0,0 +1,440 @@
+#[cfg(unix)]
+use std::os::unix::fs::DirBuilderExt;
+use std::{
+    collections::hash_map::RandomState,
+    env,
+    fs::{self, DirBuilder, File},
+    hash::{BuildHasher, Hasher},
+    io::{BufReader, ErrorKind, Read},
+    mem,
+    path::{Path, PathBuf},
+    process,
+};
+
+use claxon::FlacReader;
+use log::warn;
+
+use crate::{
+    cue::{Cue, CueIndex, FRAMES_PER_SECOND, FileKind},
+    err::{Error, Result},
+    wav::{PcmFormat, WavReader, WavWriter},
+};
+
+/// Number of samples per channel that are copied at once when splitting.
+const CHUNK_SAMPLES: u64 = 1 << 16;
+
+/// Size of raw CD sector in bytes.
+const SECTOR_BYTES: u64 = 2352;
+
+/// Audio image that is split into tracks by cue sheet.
+#[derive(Debug)]
+pub struct Image {
+    /// Files of the image in the order in which they are joined.
+    files: Vec<(PathBuf, FileKind)>,
+    pub format: PcmFormat,
+    pub tracks: Vec<ImageTrack>,
+    /// Directory with the wav files of the tracks.
+    dir: TempDir,
+}
+
+/// Position of track in the image in samples per channel from the start of
+/// the image.
+#[derive(Debug)]
+pub struct ImageTrack {
+    pub start: u64,
+    pub len: u64,
+    /// Numbers of the indices after index 1 with their positions from the
+    /// track start.
+    pub indices: Vec<(u32, u64)>,
+    /// Data tracks are not split from the image.
+    pub data: bool,
+}
+
+impl Image {
+    /// Finds the positions of the tracks from the cue sheet in the audio
+    /// files. Track ends where the next track starts, so the gaps are at the
+    /// end of the previous track. If `big_endian` is set, raw files of type
+    /// `BINARY` are in big-endian.
+    pub fn new(cue: &Cue, big_endian: bool) -> Result<Self> {
+        let mut format = None;
+        let mut files = vec![];
+        // Start of each file in the image.
+        let mut offsets = vec![];
+        let mut offset = 0;
+        for f in &cue.files {
+            let kind = match f.kind {
+                FileKind::Binary if big_endian => FileKind::Motorola,
+                k => k,
+            };
+            let reader = ImageReader::open(&f.path, kind)?;
+            if format.is_some_and(|fm| fm != reader.format()) {
+                return Err(Error::InvalidCue(
+                    "Files of the image have different format.".into(),
+                ));
+            }
+            format = Some(reader.format());
+            offsets.push(offset);
+            offset += reader.len();
+            files.push((f.path.clone(), kind));
+        }
+
+        let Some(format) = format else {
+            return Err(Error::InvalidCue("Missing audio file.".into()));
+        };
+        let rate = format.sample_rate as u64;
+        let pos =
+            |i: CueIndex| offsets[i.file] + i.pos * rate / FRAMES_PER_SECOND;
+
+        let mut tracks: Vec<ImageTrack> = vec![];
+        for t in &cue.tracks {
+            let start = t.start().map(pos).unwrap_or_default();
+            if let Some(prev) = tracks.last_mut() {
+                if start < prev.start {
+                    return Err(Error::InvalidCue(
+                        "Tracks are not in order.".into(),
+                    ));
+                }
+                prev.len = start - prev.start;
+            }
+            tracks.push(ImageTrack {
+                start,
+                len: 0,
+                indices: t
+                    .indices
+                    .iter()
+                    .filter(|i| i.number > 1)
+                    .map(|i| (i.number, pos(*i)))
+                    .filter(|(_, p)| *p >= start)
+                    .map(|(n, p)| (n, p - start))
+                    .collect(),
+                data: t.data,
+            });
+        }
+
+        if let Some(last) = tracks.last_mut() {
+            if last.start > offset {
+                return Err(Error::InvalidCue(
+                    "Track starts after the end of the image.".into(),
+                ));
+            }
+            last.len = offset - last.start;
+        }
+
+        Ok(Self {
+            files,
+            format,
+            tracks,
+            dir: TempDir::new()?,
+        })
+    }
+
+    /// Gets the path of the wav file of the track with the given index.
+    pub fn track_path(&self, idx: usize) -> PathBuf {
+        self.dir.path().join(format!("{:02}.wav", idx + 1))
+    }
+
+    /// Splits the image into wav files of the audio tracks. Audio before the
+    /// first track and data tracks are skipped.
+    pub fn split(&self) -> Result<()> {
+        let mut writer: Option<WavWriter> = None;
+        let mut next = 0;
+        let mut pos = 0;
+        let mut samples = vec![];
+        for (path, kind) in &self.files {
+            let mut reader = ImageReader::open(path, *kind)?;
+            loop {
+                let limit = match self.tracks.get(next) {
+                    Some(t) if t.start == pos => {
+                        if let Some(w) = writer.take() {
+                            w.finish()?;
+                        }
+                        if !t.data {
+                            writer = Some(WavWriter::create(
+                                self.track_path(next),
+                                self.format,
+                            )?);
+                        }
+                        next += 1;
+                        continue;
+                    }
+                    Some(t) => t.start - pos,
+                    None => u64::MAX,
+                };
+
+                let cnt = limit.min(CHUNK_SAMPLES) as usize;
+                let len = reader.read_samples(&mut samples, cnt)?;
+                if len == 0 {
+                    break;
+                }
+                if let Some(w) = &mut writer {
+                    w.write_samples(&samples)?;
+                }
+                pos += len as u64;
+            }
+        }
+
+        if let Some(w) = writer {
+            w.finish()?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads samples from audio file of the image.
+enum ImageReader {
+    Wav(WavReader),
+    Flac(FlacSamples),
+    Raw(RawSamples),
+}
+
+impl ImageReader {
+    fn open(path: &Path, kind: FileKind) -> Result<Self> {
+        let is_flac = path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("flac"));
+        match kind {
+            FileKind::Wave if is_flac => {
+                Ok(Self::Flac(FlacSamples::open(path)?))
+            }
+            FileKind::Wave => Ok(Self::Wav(WavReader::open(path)?)),
+            FileKind::Binary => Ok(Self::Raw(RawSamples::open(path, false)?)),
+            FileKind::Motorola => Ok(Self::Raw(RawSamples::open(path, true)?)),
+        }
+    }
+
+    fn format(&self) -> PcmFormat {
+        match self {
+            Self::Wav(r) => r.format,
+            Self::Flac(r) => r.format,
+            Self::Raw(_) => PcmFormat::CD,
+        }
+    }
+
+    /// Number of samples per channel.
+    fn len(&self) -> u64 {
+        match self {
+            Self::Wav(r) => r.len,
+            Self::Flac(r) => r.len,
+            Self::Raw(r) => r.len,
+        }
+    }
+
+    fn read_samples(
+        &mut self,
+        out: &mut Vec<i32>,
+        cnt: usize,
+    ) -> Result<usize> {
+        match self {
+            Self::Wav(r) => r.read_samples(out, cnt),
+            Self::Flac(r) => r.read_samples(out, cnt),
+            Self::Raw(r) => r.read_samples(out, cnt),
+        }
+    }
+}
+
+/// Reads raw 16-bit stereo samples from CD image.
+struct RawSamples {
+    data: BufReader<File>,
+    /// Number of samples per channel.
+    len: u64,
+    big_endian: bool,
+    buf: Vec<u8>,
+}
+
+impl RawSamples {
+    fn open(path: &Path, big_endian: bool) -> Result<Self> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        if !size.is_multiple_of(SECTOR_BYTES) {
+            warn!(
+                "Size of raw image {path:?} is not whole number of sectors."
+            );
+        }
+        Ok(Self {
+            data: BufReader::new(file),
+            len: size / PcmFormat::CD.block_align() as u64,
+            big_endian,
+            buf: vec![],
+        })
+    }
+
+    fn read_samples(
+        &mut self,
+        out: &mut Vec<i32>,
+        cnt: usize,
+    ) -> Result<usize> {
+        let align = PcmFormat::CD.block_align();
+        self.buf.resize(cnt * align, 0);
+        let mut len = 0;
+        while len < self.buf.len() {
+            match self.data.read(&mut self.buf[len..])? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        let len = len / align;
+
+        out.clear();
+        for s in self.buf[..len * align].chunks_exact(2) {
+            let s = [s[0], s[1]];
+            out.push(if self.big_endian {
+                i16::from_be_bytes(s)
+            } else {
+                i16::from_le_bytes(s)
+            } as i32);
+        }
+        Ok(len)
+    }
+}
+
+/// Decodes samples from flac file.
+struct FlacSamples {
+    reader: FlacReader<File>,
+    format: PcmFormat,
+    len: u64,
+    /// Decoded interleaved samples that were not read yet.
+    decoded: Vec<i32>,
+    pos: usize,
+    buf: Vec<i32>,
+}
+
+impl FlacSamples {
+    fn open(path: &Path) -> Result<Self> {
+        let reader = FlacReader::open(path)?;
+        let info = reader.streaminfo();
+        let Some(len) = info.samples else {
+            return Err(Error::InvalidFlac("Unknown number of samples."));
+        };
+        let format = PcmFormat {
+            channels: info.channels as u16,
+            sample_rate: info.sample_rate,
+            bits: info.bits_per_sample as u16,
+        };
+        Ok(Self {
+            reader,
+            format,
+            len,
+            decoded: vec![],
+            pos: 0,
+            buf: vec![],
+        })
+    }
+
+    fn read_samples(
+        &mut self,
+        out: &mut Vec<i32>,
+        cnt: usize,
+    ) -> Result<usize> {
+        let channels = self.format.channels as usize;
+        out.clear();
+        while out.len() < cnt * channels {
+            if self.pos == self.decoded.len() {
+                let buf = mem::take(&mut self.buf);
+                let Some(block) =
+                    self.reader.blocks().read_next_or_eof(buf)?
+                else {
+                    break;
+                };
+                self.decoded.clear();
+                for i in 0..block.duration() {
+                    for c in 0..block.channels() {
+                        self.decoded.push(block.sample(c, i));
+                    }
+                }
+                self.buf = block.into_buffer();
+                self.pos = 0;
+            }
+
+            let cnt = (cnt * channels - out.len())
+                .min(self.decoded.len() - self.pos);
+            out.extend_from_slice(&self.decoded[self.pos..self.pos + cnt]);
+            self.pos += cnt;
+        }
+        Ok(out.len() / channels)
+    }
+}
+
+/// Temporary directory that is removed when dropped.
+#[derive(Debug)]
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Creates new directory with random name. Existing directory is never
+    /// reused, so that it cannot be prepared by other user.
+    pub fn new() -> Result<Self> {
+        let mut builder = DirBuilder::new();
+        #[cfg(unix)]
+        builder.mode(0o700);
+
+        let mut tries = 0;
+        loop {
+            // Random state is seeded randomly.
+            let rand = RandomState::new().build_hasher().finish();
+            let path = env::temp_dir()
+                .join(format!("cdadd-{}-{rand:016x}", process::id()));
+            match builder.create(&path) {
+                Ok(_) => return Ok(Self(path)),
+                Err(e)
+                    if e.kind() == ErrorKind::AlreadyExists && tries < 16 =>
+                {
+                    tries += 1;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.0) {
+            warn!("Failed to remove temporary directory {:?}: {e}", self.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multiple_files() {
+        let dir = TempDir::new().unwrap();
+        for (name, sectors) in [("01.wav", 100), ("02.wav", 50)] {
+            let path = dir.path().join(name);
+            let mut w = WavWriter::create(path, PcmFormat::CD).unwrap();
+            w.write_samples(&vec![0; sectors * 588 * 2]).unwrap();
+            w.finish().unwrap();
+        }
+        // Gap of track 2 is at the end of the first file.
+        let cue = dir.path().join("image.cue");
+        fs::write(
+            &cue,
+            "FILE 01.wav WAVE\n\
+            TRACK 01 AUDIO\nINDEX 01 00:00:00\n\
+            TRACK 02 AUDIO\nINDEX 00 00:01:00\n\
+            FILE 02.wav WAVE\nINDEX 01 00:00:00\nINDEX 02 00:00:10\n\
+            TRACK 03 AUDIO\nINDEX 01 00:00:20\n",
+        )
+        .unwrap();
+
+        let image = Image::new(&Cue::from_file(cue).unwrap(), false).unwrap();
+        let tracks: Vec<_> = image
+            .tracks
+            .iter()
+            .map(|t| (t.start / 588, t.len / 588, t.indices.clone()))
+            .collect();
+        assert_eq!(
+            tracks,
+            [
+                (0, 100, vec![]),
+                (100, 20, vec![(2, 10 * 588)]),
+                (120, 30, vec![])
+            ]
+        );
+    }
+}
@@ -0,0 +1,427 @@
+use std::{f64::consts::PI, path::Path};
+
+use log::warn;
+
+use crate::{
+    album_info::AlbumInfo,
+    err::{Error, Result},
+    pool,
+    wav::{PcmFormat, WavReader},
+};
+
+/// Loudness that corresponds to gain of 0 dB in ReplayGain 2.0.
+const REPLAY_GAIN_REFERENCE: f64 = -18.;
+const ABSOLUTE_GATE: f64 = -70.;
+const RELATIVE_GATE: f64 = -10.;
+/// Weight of the surround channels from ITU-R BS.1770.
+const SURROUND_WEIGHT: f64 = 1.41;
+
+/// Coefficients of the interpolation filter for 4x oversampling from
+/// ITU-R BS.1770. Each row is one phase.
+const TRUE_PEAK_FILTER: [[f64; 12]; 4] = [
+    [
+        0.0017089843750,
+        0.0109863281250,
+        -0.0196533203125,
+        0.0332031250000,
+        -0.0594482421875,
+        0.1373291015625,
+        0.9721679687500,
+        -0.1022949218750,
+        0.0476074218750,
+        -0.0266113281250,
+        0.0148925781250,
+        -0.0083007812500,
+    ],
+    [
+        -0.0291748046875,
+        0.0292968750000,
+        -0.0517578125000,
+        0.0891113281250,
+        -0.1665039062500,
+        0.4650878906250,
+        0.7797851562500,
+        -0.2003173828125,
+        0.1015625000000,
+        -0.0582275390625,
+        0.0330810546875,
+        -0.0189208984375,
+    ],
+    [
+        -0.0189208984375,
+        0.0330810546875,
+        -0.0582275390625,
+        0.1015625000000,
+        -0.2003173828125,
+        0.7797851562500,
+        0.4650878906250,
+        -0.1665039062500,
+        0.0891113281250,
+        -0.0517578125000,
+        0.0292968750000,
+        -0.0291748046875,
+    ],
+    [
+        -0.0083007812500,
+        0.0148925781250,
+        -0.0266113281250,
+        0.0476074218750,
+        -0.1022949218750,
+        0.9721679687500,
+        0.1373291015625,
+        -0.0594482421875,
+        0.0332031250000,
+        -0.0196533203125,
+        0.0109863281250,
+        0.0017089843750,
+    ],
+];
+
+/// Loudness measurement of single track (EBU R128).
+pub struct Loudness {
+    /// Mean square of the gating blocks (400 ms with 75 % overlap).
+    blocks: Vec<f64>,
+    /// True peak relative to full scale.
+    pub peak: f64,
+}
+
+impl Loudness {
+    /// Measures the loudness of the wav file. Channels are weighted by
+    /// their position in the default wav channel order.
+    pub fn from_file(path: &Path) -> Result<Self> {
+        let mut wav = WavReader::open(path)?;
+        let format = wav.format;
+        Self::measure(format, |out, cnt| wav.read_samples(out, cnt))
+    }
+
+    /// Measures the loudness of audio with the given format. `read` reads
+    /// at most the given number of samples for each channel in the same way
+    /// as [`WavReader::read_samples`].
+    fn measure<F>(format: PcmFormat, mut read: F) -> Result<Self>
+    where
+        F: FnMut(&mut Vec<i32>, usize) -> Result<usize>,
+    {
+        let channels = format.channels as usize;
+        let weights = channel_weights(format.channels)?;
+        let scale = 1. / (1_u64 << (format.bits - 1)) as f64;
+        // Gating blocks are measured in steps of 100 ms.
+        let step = (format.sample_rate as usize).div_ceil(10);
+
+        let mut filters: Vec<_> = (0..channels)
+            .map(|_| KWeighting::new(format.sample_rate as f64))
+            .collect();
+        let mut peaks: Vec<_> =
+            (0..channels).map(|_| TruePeak::new()).collect();
+        let mut steps = vec![];
+        let mut samples = vec![];
+        loop {
+            let len = read(&mut samples, step)?;
+            // Incomplete step at the end is ignored.
+            if len < step {
+                for (i, s) in samples.iter().enumerate() {
+                    peaks[i % channels].push(*s as f64 * scale);
+                }
+                break;
+            }
+
+            let mut sum = 0.;
+            for (i, s) in samples.iter().enumerate() {
+                let s = *s as f64 * scale;
+                peaks[i % channels].push(s);
+                let s = filters[i % channels].filter(s);
+                sum += s * s * weights[i % channels];
+            }
+            steps.push(sum / step as f64);
+        }
+
+        let blocks = steps
+            .windows(4)
+            .map(|w| w.iter().sum::<f64>() / 4.)
+            .collect();
+        let peak = peaks.iter().map(|p| p.peak).fold(0., f64::max);
+        Ok(Self { blocks, peak })
+    }
+
+    /// Gets the integrated loudness in LUFS. Returns [`None`] if the track
+    /// is too short or silent.
+    pub fn integrated(&self) -> Option<f64> {
+        integrated(&self.blocks)
+    }
+}
+
+/// Measures the loudness of all tracks in the album and sets their
+/// ReplayGain 2.0 values.
+pub fn replay_gain(album: &mut AlbumInfo, jobs: usize) {
+    let mut res: Vec<_> = album.tracks.iter().map(|_| None).collect();
+    pool::run(
+        jobs,
+        album.tracks.iter().map(|(_, p)| p).enumerate(),
+        |(i, p)| (i, Loudness::from_file(p)),
+        |(i, l)| match l {
+            Ok(l) => res[i] = Some(l),
+            Err(e) => {
+                warn!(
+                    "Failed to measure loudness of {:?}: {e}",
+                    album.tracks[i].1
+                )
+            }
+        },
+    );
+
+    // Album gain is valid only if all the tracks were measured.
+    let (album_gain, album_peak) = if res.iter().all(|r| r.is_some()) {
+        let blocks: Vec<_> = res
+            .iter()
+            .flatten()
+            .flat_map(|l| &l.blocks)
+            .copied()
+            .collect();
+        let peak = res.iter().flatten().map(|l| l.peak).fold(0., f64::max);
+        (integrated(&blocks).map(gain), Some(peak))
+    } else {
+        (None, None)
+    };
+
+    for ((t, _), l) in album.tracks.iter_mut().zip(res) {
+        t.track_gain = l.as_ref().and_then(|l| l.integrated()).map(gain);
+        t.track_peak = l.map(|l| l.peak);
+        t.album_gain = album_gain;
+        t.album_peak = album_peak;
+    }
+}
+
+/// Gets the weights of the channels. The layout is guessed from the number of
+/// channels: mono, stereo, 3.0, 5.0, 5.1 or 7.1. LFE is excluded.
+fn channel_weights(channels: u16) -> Result<Vec<f64>> {
+    let s = SURROUND_WEIGHT;
+    match channels {
+        1..=3 => Ok(vec![1.; channels as usize]),
+        5 => Ok(vec![1., 1., 1., s, s]),
+        6 => Ok(vec![1., 1., 1., 0., s, s]),
+        8 => Ok(vec![1., 1., 1., 0., s, s, s, s]),
+        _ => Err(Error::UnknownLayout(channels)),
+    }
+}
+
+/// Converts loudness to ReplayGain 2.0 gain.
+fn gain(loudness: f64) -> f64 {
+    REPLAY_GAIN_REFERENCE - loudness
+}
+
+/// Computes gated loudness from the mean squares of the gating blocks.
+fn integrated(blocks: &[f64]) -> Option<f64> {
+    fn mean(blocks: impl Iterator<Item = f64>) -> Option<f64> {
+        let (sum, cnt) = blocks.fold((0., 0), |(s, c), b| (s + b, c + 1));
+        (cnt != 0).then(|| sum / cnt as f64)
+    }
+
+    let abs = mean(
+        blocks
+            .iter()
+            .copied()
+            .filter(|b| loudness(*b) > ABSOLUTE_GATE),
+    )?;
+    let gate = loudness(abs) + RELATIVE_GATE;
+    let rel = mean(
+        blocks
+            .iter()
+            .copied()
+            .filter(|b| loudness(*b) > ABSOLUTE_GATE && loudness(*b) > gate),
+    )?;
+    Some(loudness(rel))
+}
+
+/// Converts weighted sum of the mean squares of the channels to loudness.
+fn loudness(mean_square: f64) -> f64 {
+    -0.691 + 10. * mean_square.log10()
+}
+
+/// K-weighting filter from ITU-R BS.1770 for any sample rate.
+struct KWeighting {
+    shelf: Biquad,
+    high_pass: Biquad,
+}
+
+impl KWeighting {
+    fn new(rate: f64) -> Self {
+        // High shelf.
+        let f0 = 1681.974450955533;
+        let g = 3.999843853973347;
+        let q = 0.7071752369554196;
+        let k = (PI * f0 / rate).tan();
+        let vh = 10_f64.powf(g / 20.);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1. + k / q + k * k;
+        let shelf = Biquad::new(
+            [
+                (vh + vb * k / q + k * k) / a0,
+                2. * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            [2. * (k * k - 1.) / a0, (1. - k / q + k * k) / a0],
+        );
+
+        // High pass.
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+        let k = (PI * f0 / rate).tan();
+        let a0 = 1. + k / q + k * k;
+        let high_pass = Biquad::new(
+            [1., -2., 1.],
+            [2. * (k * k - 1.) / a0, (1. - k / q + k * k) / a0],
+        );
+
+        Self { shelf, high_pass }
+    }
+
+    fn filter(&mut self, x: f64) -> f64 {
+        self.high_pass.filter(self.shelf.filter(x))
+    }
+}
+
+/// Second order IIR filter in direct form II.
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    z: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Self { b, a, z: [0.; 2] }
+    }
+
+    fn filter(&mut self, x: f64) -> f64 {
+        let w = x - self.a[0] * self.z[0] - self.a[1] * self.z[1];
+        let y = self.b[0] * w + self.b[1] * self.z[0] + self.b[2] * self.z[1];
+        self.z = [w, self.z[0]];
+        y
+    }
+}
+
+/// Measures true peak of single channel by oversampling it 4 times.
+struct TruePeak {
+    /// Last samples, the newest is first.
+    history: [f64; 12],
+    peak: f64,
+}
+
+impl TruePeak {
+    fn new() -> Self {
+        Self {
+            history: [0.; 12],
+            peak: 0.,
+        }
+    }
+
+    fn push(&mut self, x: f64) {
+        self.history.copy_within(..11, 1);
+        self.history[0] = x;
+        self.peak = self.peak.max(x.abs());
+        for phase in &TRUE_PEAK_FILTER {
+            let y: f64 =
+                phase.iter().zip(&self.history).map(|(c, x)| c * x).sum();
+            self.peak = self.peak.max(y.abs());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Measures the interleaved samples.
+    fn measure(format: PcmFormat, samples: &[i32]) -> Loudness {
+        let channels = format.channels as usize;
+        let mut rest = samples;
+        Loudness::measure(format, |out, cnt| {
+            let len = (cnt * channels).min(rest.len());
+            out.clear();
+            out.extend_from_slice(&rest[..len]);
+            rest = &rest[len..];
+            Ok(len / channels)
+        })
+        .unwrap()
+    }
+
+    /// Generates stereo sine with the given frequency, amplitude in dBFS and
+    /// length in seconds.
+    fn sine(format: PcmFormat, freq: f64, dbfs: f64, secs: f64) -> Vec<i32> {
+        let amp = 10_f64.powf(dbfs / 20.) * 32767.;
+        let len = (format.sample_rate as f64 * secs) as usize;
+        (0..len)
+            .flat_map(|i| {
+                let t = i as f64 / format.sample_rate as f64;
+                let s = (amp * (2. * PI * freq * t).sin()).round() as i32;
+                [s; 2]
+            })
+            .collect()
+    }
+
+    fn format(sample_rate: u32) -> PcmFormat {
+        PcmFormat {
+            channels: 2,
+            sample_rate,
+            bits: 16,
+        }
+    }
+
+    #[test]
+    fn sine_loudness() {
+        // Reference from EBU Tech 3341: stereo sine at 997 Hz and -20 dBFS
+        // has loudness -20 LUFS.
+        for rate in [44100, 48000] {
+            let format = format(rate);
+            let l = measure(format, &sine(format, 997., -20., 5.));
+            let lufs = l.integrated().unwrap();
+            assert!((lufs + 20.).abs() < 0.1, "{rate}: {lufs}");
+            assert!((l.peak - 0.1).abs() < 0.001, "{rate}: {}", l.peak);
+        }
+    }
+
+    #[test]
+    fn relative_gate() {
+        let format = format(48000);
+        let mut samples = sine(format, 997., -20., 10.);
+        samples.extend(sine(format, 997., -40., 10.));
+        let lufs = measure(format, &samples).integrated().unwrap();
+        // The quiet part is below the relative gate.
+        assert!((lufs + 20.).abs() < 0.1, "{lufs}");
+    }
+
+    #[test]
+    fn absolute_gate() {
+        let format = format(48000);
+        assert_eq!(measure(format, &[0; 96000 * 2]).integrated(), None);
+        let quiet = sine(format, 997., -80., 2.);
+        assert_eq!(measure(format, &quiet).integrated(), None);
+        // Shorter than single gating block.
+        let short = sine(format, 997., -20., 0.3);
+        assert_eq!(measure(format, &short).integrated(), None);
+    }
+
+    #[test]
+    fn true_peak() {
+        // Sine at quarter of the sample rate with phase of 45° has samples at
+        // 0.707 of its peak.
+        let mut peak = TruePeak::new();
+        let samples = (0..100).map(|i| (PI / 2. * i as f64 + PI / 4.).sin());
+        let mut sample_peak: f64 = 0.;
+        for s in samples {
+            sample_peak = sample_peak.max(s.abs());
+            peak.push(s);
+        }
+        assert!((sample_peak - 0.707).abs() < 0.001);
+        assert!(peak.peak > 0.95 && peak.peak < 1.05, "{}", peak.peak);
+    }
+
+    #[test]
+    fn weights() {
+        assert_eq!(channel_weights(2).unwrap(), [1., 1.]);
+        assert_eq!(
+            channel_weights(6).unwrap(),
+            [1., 1., 1., 0., SURROUND_WEIGHT, SURROUND_WEIGHT]
+        );
+        assert!(channel_weights(4).is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{err::Result, track_info::TrackInfo};
+
+/// Extensions of lyrics files in the order of preference.
+const LYRICS_EXTENSIONS: &[&str] = &["lrc", "txt"];
+
+/// Lyrics of single track.
+#[derive(Debug)]
+pub struct Lyrics {
+    /// Lyrics without timestamps.
+    pub text: String,
+    /// Lrc file with synchronized lyrics.
+    pub lrc: Option<PathBuf>,
+}
+
+impl Lyrics {
+    /// Loads lyrics from `.lrc` or `.txt` file.
+    pub fn from_file<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)?;
+        if path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("lrc"))
+        {
+            Ok(Self {
+                text: lrc_text(&data),
+                lrc: Some(path.to_owned()),
+            })
+        } else {
+            Ok(Self {
+                text: data.trim().to_owned(),
+                lrc: None,
+            })
+        }
+    }
+
+    /// Finds all the lyrics files in the given directory.
+    pub fn files(dir: &Path) -> Vec<PathBuf> {
+        let Ok(dir) = fs::read_dir(dir) else {
+            return vec![];
+        };
+        dir.flatten()
+            .map(|f| f.path())
+            .filter(|p| {
+                p.extension().and_then(|e| e.to_str()).is_some_and(|e| {
+                    LYRICS_EXTENSIONS.iter().any(|l| l.eq_ignore_ascii_case(e))
+                })
+            })
+            .collect()
+    }
+
+    /// Finds lyrics file for the track from `wav` in `files`. The lyrics
+    /// file must have the same name as the wav file or as the track title,
+    /// or it must start with the track number. Matches are preferred in this
+    /// order.
+    pub fn find<'a>(
+        files: &'a [PathBuf],
+        wav: &Path,
+        track: &TrackInfo,
+    ) -> Option<&'a PathBuf> {
+        let wav = wav.file_stem().and_then(|s| s.to_str());
+        let rank = |p: &PathBuf| {
+            let name = p.file_stem().and_then(|s| s.to_str())?;
+            let digits = name.find(|c: char| !c.is_ascii_digit());
+            let num = name[..digits.unwrap_or(name.len())].parse().ok();
+            if wav == Some(name) {
+                Some(0)
+            } else if track
+                .title
+                .as_ref()
+                .is_some_and(|t| t.eq_ignore_ascii_case(name))
+            {
+                Some(1)
+            } else if num.is_some() && num == track.track {
+                Some(2)
+            } else {
+                None
+            }
+        };
+
+        // Prefer the extensions in the order of `LYRICS_EXTENSIONS`.
+        let ext = |p: &PathBuf| {
+            let ext = p.extension().and_then(|e| e.to_str()).unwrap_or("");
+            LYRICS_EXTENSIONS
+                .iter()
+                .position(|l| l.eq_ignore_ascii_case(ext))
+        };
+        files
+            .iter()
+            .filter_map(|p| rank(p).map(|r| (r, ext(p), p)))
+            .min()
+            .map(|(_, _, p)| p)
+    }
+}
+
+/// Removes the timestamps and the metadata from lrc lyrics.
+fn lrc_text(lrc: &str) -> String {
+    let lines: Vec<_> = lrc
+        .lines()
+        .filter_map(|l| {
+            let mut l = l.trim();
+            while let Some(rest) = l.strip_prefix('[') {
+                let (tag, rest) = rest.split_once(']')?;
+                // Tags such as `[ar:Artist]` are metadata.
+                if !tag.starts_with(|c: char| c.is_ascii_digit()) {
+                    return None;
+                }
+                l = rest.trim_start();
+            }
+            Some(l)
+        })
+        .collect();
+    lines.join("\n").trim().to_owned()
+}
@@ -7,26 +7,62 @@ use std::{
 };
 
 use album_info::AlbumInfo;
-use err::Result;
+use err::{Error, Result};
 use flexi_logger::Logger;
+use log::error;
 use pareg::Pareg;
 use termal::{printmcln, raw::readers::prompt_to};
 use track_info::TrackInfo;
 
-use crate::cli::{Action, Args};
+use crate::{
+    cli::{Action, Args},
+    cover::Cover,
+    encode::Target,
+    summary::{Outcome, Summary, TrackResult},
+};
 
 mod album_info;
+mod bit_writer;
 mod cddb_read;
+mod cdindex;
+mod cdtext;
 mod cli;
+mod config;
+mod cover;
+mod cue;
 mod date;
+mod encode;
+mod encoder;
 mod err;
 mod flac;
+mod flac_enc;
+mod flac_meta;
 mod get_perf;
+mod image;
+mod loudness;
+mod lyrics;
+mod mp3;
+mod opus;
+mod pool;
+mod progress;
+mod sort;
+mod summary;
+mod tag_map;
+mod tags;
 mod track_info;
+mod wav;
+
+/// Exit code used when the run finished, but some of the tracks failed to
+/// encode.
+const ENCODE_FAILURE: u8 = 2;
 
 fn main() -> ExitCode {
     match start() {
         Ok(()) => ExitCode::SUCCESS,
+        Err(e @ (Error::EncodeFailed(..) | Error::TagFailed(..))) => {
+            println!("{e}");
+            ExitCode::from(ENCODE_FAILURE)
+        }
         Err(e) => {
             println!("{e}");
             ExitCode::FAILURE
@@ -41,6 +77,7 @@ fn start() -> Result<()> {
     match args.action() {
         Action::Help => help(),
         Action::Encode(p) => encode(&args, p.as_ref())?,
+        Action::Tag(p) => tag(&args, p.as_ref())?,
     }
     Ok(())
 }
@@ -65,7 +102,24 @@ Version {v}
 
   {'c}cdadd {'w}-e <path> {'gr}[output dir] [{'dg}flags{'gr}]{'_}
     Encodes album in the folder given by {'w}path{'_}. Use {'y}-o{'_} if the
-    {'gr}output dir{'_} starts with {'bold}-{'_}.
+    {'gr}output dir{'_} starts with {'bold}-{'_}. Exits with code {'w}2{'_} if any of
+    the tracks fails to encode. Flac files that are already encoded are
+    skipped and only their metadata are updated if needed. Flac files are
+    verified against the source after encoding. ReplayGain 2.0 is computed
+    for all the tracks. Lyrics are loaded from {'i}.lrc{'_} or {'i}.txt{'_} files
+    named by the track number, title or the wav file. Folders from
+    {'w}cdparanoia{'_} ({'i}track01.cdda.wav{'_}) are also supported. The folder may also
+    contain single {'i}.wav{'_} or {'i}.flac{'_} image with {'i}.cue{'_} sheet instead of
+    the tracks, or {'w}path{'_} may be the cue sheet. The image is split into
+    the tracks at their index 1. The image may be split into multiple
+    files. Raw {'i}.bin{'_} images with 2352 byte
+    sectors are also supported, data tracks are skipped.
+
+  {'c}cdadd {'w}-t <path>{'_}
+    Edits the metadata of flac files in the folder given by {'w}path{'_} in the
+    interactive editor. Only the metadata are rewritten, the audio is not
+    encoded again. The tag action is given as flag like {'w}-e{'_}, not as
+    subcommand.
 
 {'g}Flags:
   {'y}-h  -?  --help{'_}
@@ -74,12 +128,70 @@ Version {v}
   {'y}-e  --encode {'w}<path>{'_}
     Encodes album in the folder given by {'w}path{'_}.
 
+  {'y}-t  --tag {'w}<path>{'_}
+    Edits metadata of flac files in the folder given by {'w}path{'_}.
+
   {'y}-i  --interactive{'_}
     Enables interactive mode for metadata.
 
-  {'y}-o  --output{'_}
-    Sets the output directory. This is {'i}cwd{'_} by default.
-    
+  {'y}-o  --output {'gr}[{'w}<format>{'gr}:]{'w}<dir>{'_}
+    Adds output directory. If {'w}format{'_} is not given, the format given by
+    {'y}-f{'_} is used. Can be used multiple times to encode into multiple
+    formats at once, for example {'i}-o flac:lossless -o opus:portable{'_}.
+    This is {'i}cwd{'_} by default.
+
+  {'y}-j  --jobs {'w}<count>{'_}
+    Sets the maximum number of tracks that are encoded at once. This is the
+    number of CPUs by default.
+
+  {'y}-f  --format {'w}<flac|opus|mp3>{'_}
+    Sets the default output format. This is {'i}flac{'_} by default. Opus is encoded
+    using the external program {'w}opusenc{'_} and mp3 using {'w}lame{'_}.
+
+  {'y}--mp3-preset {'w}<v0|v2|cbr|cbr:<kbps>>{'_}
+    Sets the quality of mp3. {'w}cbr{'_} is constant bitrate of 320 kbps. The
+    bitrate of {'w}cbr:<kbps>{'_} must be one of the bitrates supported by
+    {'w}lame{'_}. This is {'i}v2{'_} by default.
+
+  {'y}--external{'_}
+    Use the external program {'w}flac{'_} to encode flac instead of the
+    built-in encoder.
+
+  {'y}--force{'_}
+    Encode all the tracks even if they are already encoded.
+
+  {'y}--no-verify{'_}
+    Don't check that the flac files decode to the same audio as the source
+    wav files.
+
+  {'y}--big-endian{'_}
+    Raw {'i}.bin{'_} images of type {'w}BINARY{'_} in cue sheet are in big-endian.
+    Images of type {'w}MOTOROLA{'_} are always big-endian.
+
+  {'y}--cover {'w}<path>{'_}
+    Image (jpeg or png) used as front cover. By default, {'i}cover{'_}, {'i}folder{'_}
+    or {'i}front{'_} image from the source folder is used.
+
+  {'y}-s  --set {'w}<format>.<setting>=<value>{'_}
+    Sets encoder setting for the given format. This overrides the setting
+    from the config file. Supported settings are:
+    {'w}level{'_}       compression level (flac 0-8, opus 0-10, mp3 0-9).
+    {'w}block-size{'_}  block size in samples (only flac).
+    {'w}padding{'_}     size of padding for later retagging (only flac).
+    {'w}verify{'_}      {'i}true{'_} or {'i}false{'_} (only flac).
+    {'w}args{'_}        extra arguments for the external encoder.
+
+  {'y}--config {'w}<path>{'_}
+    Loads the config from the given file instead of the default
+    {'i}$XDG_CONFIG_HOME/cdadd/config.ini{'_}. The config file has section for
+    each format with the same settings as {'y}--set{'_}. Section {'w}[sort]{'_}
+    contains sort names of artists as {'i}<name> = <sort name>{'_}. Empty sort
+    name means that the last name of the person goes first. Section
+    {'w}[album]{'_} may set {'w}various-artists{'_}, the album artist of
+    compilations. Section {'w}[tags]{'_} maps fields to names of vorbis
+    comments as {'i}<field> = <names>{'_} (empty to not write the field) and
+    {'i}<field>.join = <separator>{'_} to join multiple values.
+
  “ {'i}Teach me your way, Lord, that I may rely on your faithfulness;
    give me an undivided heart, that I may fear your name.         {'_}”
                                                      {'w bold}✝ Psalms 86:11{'_}
@@ -88,21 +200,93 @@ Version {v}
 }
 
 fn encode(args: &Args, src: &str) -> Result<()> {
-    let mut album = AlbumInfo::from_dir(src)?;
+    let config = args.config()?;
+    let mut album = AlbumInfo::from_dir(src, args.big_endian)?;
+    if let Some(cover) = &args.cover {
+        album.set_cover(Cover::from_file(cover)?);
+    }
+    album.fill_from_config(&config);
     if args.interactive && !configure(&mut album)? {
         return Ok(());
     }
+    // Values changed in the editor are generated again.
+    album.fill_from_config(&config);
     album.normalize();
+    if album.image.is_some() {
+        println!("Splitting image...");
+        album.split_image()?;
+    }
+    println!("Measuring loudness...");
+    loudness::replay_gain(&mut album, args.jobs());
     println!("Encoding:");
-    flac::encode(&album, args.output())?;
-    Ok(())
+    let targets: Vec<_> = args
+        .outputs()
+        .into_iter()
+        .map(|(format, dir)| {
+            let verify = config.encoder(format).verify.unwrap_or(true);
+            Target {
+                dir,
+                encoder: format.encoder(args, &config),
+                verify: !args.no_verify && verify,
+            }
+        })
+        .collect();
+    let mut summary =
+        encode::encode(&album, &targets, args.jobs(), args.force)?;
+    println!();
+    summary.print();
+
+    match summary.failed() {
+        0 => Ok(()),
+        n => Err(Error::EncodeFailed(n, summary.tracks.len())),
+    }
+}
+
+fn tag(args: &Args, dir: &str) -> Result<()> {
+    let config = args.config()?;
+    let mut album = AlbumInfo::from_flac_dir(dir, &config.tags)?;
+    album.fill_from_config(&config);
+    if !configure(&mut album)? {
+        return Ok(());
+    }
+    album.fill_from_config(&config);
+    album.normalize();
+
+    let mut summary = Summary::default();
+    for (i, (t, path)) in album.tracks.iter().enumerate() {
+        let res = flac::retag(path, t, &config.tags).map(|changed| {
+            if changed {
+                Outcome::Retagged
+            } else {
+                Outcome::Skipped
+            }
+        });
+        if let Err(e) = &res {
+            error!("Failed to tag {path:?}: {e}");
+        }
+        summary.push(TrackResult {
+            track: t.track.unwrap_or(i + 1),
+            path: path.clone(),
+            res,
+            verified: None,
+        });
+    }
+    println!();
+    summary.print();
+
+    match summary.failed() {
+        0 => Ok(()),
+        n => Err(Error::TagFailed(n, summary.tracks.len())),
+    }
 }
 
 fn print_album(album: &AlbumInfo) {
     println!("Album    : {}", field_str(album.album_title.as_ref()));
     println!("Disc name: {}", field_str(album.disc_name.as_ref()));
     println!("Artist   : {}", field_str(album.artist.as_ref()));
+    println!("Art. sort: {}", field_str(album.artist_sort.as_ref()));
     println!("Disc     : {}", field_str(album.disc));
+    println!("Discs    : {}", field_str(album.disc_total));
     println!("CDINDEX  : {}", field_str(album.cdindex.as_ref()));
     println!(
         "CDDB     : {}",
@@ -112,6 +296,17 @@ fn print_album(album: &AlbumInfo) {
     );
     println!("Date     : {}", field_str(album.date));
     println!("Genre    : {}", field_str(album.genre.as_ref()));
+    println!("Label    : {}", field_str(album.label.as_ref()));
+    println!("Catalog  : {}", field_str(album.catalog.as_ref()));
+    println!("Barcode  : {}", field_str(album.barcode.as_ref()));
+    println!(
+        "Compil.  : {}",
+        if album.compilation { "yes" } else { "no" }
+    );
+    println!(
+        "Cover    : {}",
+        field_str(album.cover.as_ref().map(|c| c.path.to_string_lossy()))
+    );
 
     for (s, f) in album.tracks.iter() {
         println!();
@@ -123,13 +318,28 @@ fn print_track(song: &TrackInfo, file: &Path) {
     println!("File        : {}", file.to_string_lossy());
     println!("Title       : {}", field_str(song.title.as_ref()));
     println!("Track       : {}", field_str(song.track));
+    println!("Tracks      : {}", field_str(song.track_total));
     println!("Artist      : {}", field_str(song.artist.as_ref()));
+    println!("Artist sort : {}", field_str(song.artist_sort.as_ref()));
     println!("ISRC        : {}", field_str(song.isrc.as_ref()));
+    println!("Composer    : {}", field_str(song.composer.as_ref()));
+    println!("Lyricist    : {}", field_str(song.lyricist.as_ref()));
+    println!("Arranger    : {}", field_str(song.arranger.as_ref()));
+    println!("Comment     : {}", field_str(song.comment.as_ref()));
+    let lyrics = song.lyrics.as_ref().map(|l| match &l.lrc {
+        Some(lrc) => format!("synced ({})", lrc.to_string_lossy()),
+        None => "yes".to_owned(),
+    });
+    println!("Lyrics      : {}", field_str(lyrics));
     println!("Date        : {}", field_str(song.date));
     println!("Genre       : {}", field_str(song.genre.as_ref()));
     println!("Album       : {}", field_str(song.album.as_ref()));
     println!("Disc name   : {}", field_str(song.disc_name.as_ref()));
     println!("Album artist: {}", field_str(song.album_artist.as_ref()));
+    println!(
+        "Alb.art sort: {}",
+        field_str(song.album_artist_sort.as_ref())
+    );
     let ats = song.feat.join(", ");
     if ats.is_empty() {
         println!("Featuring   : --",);
@@ -137,6 +347,7 @@ fn print_track(song: &TrackInfo, file: &Path) {
         println!("Featuring   : {ats}");
     }
     println!("Disc        : {}", field_str(song.disc));
+    println!("Discs       : {}", field_str(song.disc_total));
     println!("CDINDEX     : {}", field_str(song.cdindex.as_ref()));
     println!(
         "CDDB        : {}",
@@ -181,6 +392,28 @@ fn configure(album: &mut AlbumInfo) -> Result<bool> {
         let fld = fld.trim().to_ascii_lowercase();
         value = value.trim();
 
+        if let Some((n, fld)) = fld.split_once('.') {
+            let Ok(n) = n.trim().parse::<usize>() else {
+                println!("Invalid track number '{n}'");
+                continue;
+            };
+            let track = album
+                .tracks
+                .iter_mut()
+                .enumerate()
+                .find(|(i, (t, _))| t.track.unwrap_or(i + 1) == n);
+            let Some((_, (t, _))) = track else {
+                println!("There is no track {n}");
+                continue;
+            };
+            if !set_track_field(t, fld.trim(), value) {
+                println!("Unknown track field '{}'", fld.trim());
+                continue;
+            }
+            print_album(album);
+            continue;
+        }
+
         match fld.as_ref() {
             "dn" | "disc-name" => {
                 album.disc_name = Some(value.to_owned());
@@ -196,9 +429,22 @@ fn configure(album: &mut AlbumInfo) -> Result<bool> {
             }
             "artist" | "album-artist" => {
                 album.artist = Some(value.to_owned());
+                album.artist_sort = None;
+                album.artist_auto = false;
                 for (t, _) in album.tracks.iter_mut() {
                     t.album_artist = Some(value.to_owned());
+                    t.album_artist_sort = None;
+                }
+            }
+            "as" | "artist-sort" | "album-artist-sort" => {
+                // Tracks by the album artist are sorted the same way.
+                for (t, _) in album.tracks.iter_mut() {
+                    if t.artist.is_some() && t.artist == album.artist {
+                        t.artist_sort = Some(value.to_owned());
+                    }
+                    t.album_artist_sort = Some(value.to_owned());
                 }
+                album.artist_sort = Some(value.to_owned());
             }
             "disc" => {
                 let disc = match value.parse() {
@@ -213,6 +459,34 @@ fn configure(album: &mut AlbumInfo) -> Result<bool> {
                     t.disc = Some(disc);
                 }
             }
+            "comp" | "compilation" => {
+                let comp = match value.to_ascii_lowercase().as_str() {
+                    "yes" | "true" | "1" => true,
+                    "no" | "false" | "0" => false,
+                    _ => {
+                        println!("Expected 'yes' or 'no' for compilation.");
+                        continue;
+                    }
+                };
+                album.compilation = comp;
+                for (t, _) in album.tracks.iter_mut() {
+                    t.compilation = comp;
+                }
+            }
+            "dt" | "disc-total" => {
+                let total = match value.parse() {
+                    Ok(0) => None,
+                    Ok(d) => Some(d),
+                    Err(e) => {
+                        println!("Failed to parse disc total: {e}");
+                        continue;
+                    }
+                };
+                album.disc_total = total;
+                for (t, _) in album.tracks.iter_mut() {
+                    t.disc_total = total;
+                }
+            }
             "cdindex" | "cdindex-discid" => {
                 album.cdindex = Some(value.to_owned());
                 for (t, _) in album.tracks.iter_mut() {
@@ -251,6 +525,22 @@ fn configure(album: &mut AlbumInfo) -> Result<bool> {
                     t.genre = Some(value.to_owned());
                 }
             }
+            "label" | "catalog" | "catalog-number" | "barcode" | "upc" => {
+                let value = (!value.is_empty()).then(|| value.to_owned());
+                match fld.as_ref() {
+                    "label" => album.label = value.clone(),
+                    "barcode" | "upc" => album.barcode = value.clone(),
+                    _ => album.catalog = value.clone(),
+                }
+                for (t, _) in album.tracks.iter_mut() {
+                    set_track_field(t, &fld, value.as_deref().unwrap_or(""));
+                }
+            }
+            "composer" | "lyricist" | "arranger" | "comment" => {
+                for (t, _) in album.tracks.iter_mut() {
+                    set_track_field(t, &fld, value);
+                }
+            }
             _ => {
                 println!("Unknown album field '{fld}'");
             }
@@ -259,6 +549,24 @@ fn configure(album: &mut AlbumInfo) -> Result<bool> {
     }
 }
 
+/// Sets field that may differ for each track. Empty value unsets the field.
+/// Returns `false` if the field is unknown.
+fn set_track_field(track: &mut TrackInfo, field: &str, value: &str) -> bool {
+    let value = (!value.is_empty()).then(|| value.to_owned());
+    match field {
+        "composer" => track.composer = value,
+        "lyricist" => track.lyricist = value,
+        "arranger" => track.arranger = value,
+        "as" | "artist-sort" => track.artist_sort = value,
+        "comment" => track.comment = value,
+        "label" => track.label = value,
+        "catalog" | "catalog-number" => track.catalog = value,
+        "barcode" | "upc" => track.barcode = value,
+        _ => return false,
+    }
+    true
+}
+
 fn command_help() {
     let is_term = io::stdout().is_terminal();
     let sign: Cow<str> = if is_term {
@@ -273,15 +581,19 @@ fn command_help() {
   {'r}<field>{'w}=<value>{'_}
     Set the given field for all songs in the album.
 
+  {'w}<track>.{'r}<field>{'w}=<value>{'_}
+    Set the given field only for the track with the given number. This is
+    supported only by the track fields.
+
   {'w}:{'c}<command>{'_}
     Run the given command.
 
 {'g}Commands:
   {'c}d  done{'_}
-    Encode and exit.
+    Encode (or save the metadata when tagging) and exit.
 
   {'c}c  cancel  quit{'_}
-    Exit without encoding.
+    Exit without encoding or saving.
 
   {'c}h  help{'_}
     Print this help.
@@ -296,9 +608,20 @@ fn command_help() {
   {'r}artist  album-artist{'w}=<string>{'_}
     Album artist.
 
+  {'r}as  artist-sort  album-artist-sort{'w}=<string>{'_}
+    Name used to sort the album artist. It is generated by moving the leading
+    article to the end or from the {'w}[sort]{'_} section of the config file.
+
   {'r}disc{'w}=<uint>{'_}
     Disc number. (0 for no disc number).
 
+  {'r}comp  compilation{'w}=<yes|no>{'_}
+    Album with tracks by various artists. Album artist of compilation is
+    {'i}Various Artists{'_} unless set.
+
+  {'r}dt  disc-total{'w}=<uint>{'_}
+    Number of discs in the set. (0 for no disc total).
+
   {'r}cdindex  cdindex-discid{'w}=<string>{'_}
     CdIndex disc id.
 
@@ -311,6 +634,34 @@ fn command_help() {
 
   {'r}genre{'w}=<string>{'_}
     Music genre.
+
+{'g}Track fields:
+  {'r}composer{'w}=<string>{'_}
+    Composer of the music.
+
+  {'r}lyricist{'w}=<string>{'_}
+    Author of the lyrics.
+
+  {'r}arranger{'w}=<string>{'_}
+    Arranger of the music.
+
+  {'r}as  artist-sort{'w}=<string>{'_}
+    Name used to sort the track artist. Empty value generates it again from
+    the artist.
+
+  {'r}comment{'w}=<string>{'_}
+    Free-form comment.
+
+  {'r}label{'w}=<string>{'_}
+    Record label.
+
+  {'r}catalog  catalog-number{'w}=<string>{'_}
+    Catalog number given by the label.
+
+  {'r}barcode  upc{'w}=<string>{'_}
+    Barcode (UPC/EAN) of the release.
+
+  Empty value unsets the track field.
 "
     );
 }
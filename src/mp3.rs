@@ -0,0 +1,230 @@
+use std::{fmt::Display, path::Path, process::Command, str::FromStr};
+
+use id3::{
+    Tag, TagLike, Version,
+    frame::{Comment, ExtendedText, Lyrics, Picture, PictureType},
+};
+use pareg::{ArgError, FromArgStr};
+
+use crate::{
+    config::EncoderSettings,
+    encoder::{self, Encoder},
+    err::Result,
+    tags,
+    track_info::TrackInfo,
+};
+
+/// Encodes into mp3 using the external program `lame`. The files are tagged
+/// with ID3v2.4.
+pub struct Mp3 {
+    pub preset: Mp3Preset,
+    pub settings: EncoderSettings,
+}
+
+/// Bitrates in kbps supported by lame in CBR mode.
+const CBR_BITRATES: [u32; 17] = [
+    8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+];
+
+/// Quality preset of lame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Mp3Preset {
+    /// Highest quality VBR.
+    V0,
+    /// Standard quality VBR.
+    #[default]
+    V2,
+    /// Constant bitrate in kbps.
+    Cbr(u32),
+}
+
+impl Encoder for Mp3 {
+    fn extension(&self) -> &'static str {
+        "mp3"
+    }
+
+    fn describe(&self) -> String {
+        let level = self
+            .settings
+            .level
+            .map_or_else(String::new, |l| format!(", level {l}"));
+        let args = encoder::describe_args(&self.settings.args);
+        format!("mp3, preset {}{level}{args}", self.preset)
+    }
+
+    fn encode(
+        &self,
+        src: &Path,
+        dst: &Path,
+        track: &TrackInfo,
+        progress: &dyn Fn(u64),
+    ) -> Result<()> {
+        let mut cmd = Command::new("lame");
+        cmd.arg("--quiet");
+        match self.preset {
+            Mp3Preset::V0 => cmd.args(["-V", "0"]),
+            Mp3Preset::V2 => cmd.args(["-V", "2"]),
+            Mp3Preset::Cbr(b) => cmd.args(["--cbr", "-b", &b.to_string()]),
+        };
+        if let Some(level) = self.settings.level {
+            cmd.args(["-q", &level.to_string()]);
+        }
+        cmd.args(&self.settings.args);
+        cmd.arg("-");
+        cmd.arg(dst);
+        encoder::run(&mut cmd, src, progress)?;
+
+        id3_tag(track).write_to_path(dst, Version::Id3v24)?;
+        Ok(())
+    }
+}
+
+fn id3_tag(track: &TrackInfo) -> Tag {
+    let mut tag = Tag::new();
+
+    let mut set = |id: &str, value: Option<String>| {
+        if let Some(value) = value {
+            tag.set_text(id, value);
+        }
+    };
+
+    set("TIT2", track.title.clone());
+    set("TALB", track.album.clone());
+    set("TSST", track.disc_name.clone());
+    set("TPE2", track.album_artist.clone());
+    set("TSOP", track.artist_sort.clone());
+    set("TSO2", track.album_artist_sort.clone());
+    set("TCMP", track.compilation.then(|| "1".to_owned()));
+    set("TDRC", track.date.map(|d| d.to_string()));
+    set("TCON", track.genre.clone());
+    set("TSRC", track.isrc.clone());
+    set("TCOM", track.composer.clone());
+    set("TEXT", track.lyricist.clone());
+    set("TPUB", track.label.clone());
+    set(
+        "TRCK",
+        track.track.map(|t| match track.track_total {
+            Some(n) => format!("{t}/{n}"),
+            None => t.to_string(),
+        }),
+    );
+    set(
+        "TPOS",
+        track.disc.map(|d| match track.disc_total {
+            Some(n) => format!("{d}/{n}"),
+            None => d.to_string(),
+        }),
+    );
+
+    if let Some(arranger) = &track.arranger {
+        tag.set_text_values("TIPL", ["arranger", arranger]);
+    }
+
+    let artists: Vec<_> = track.artist.iter().chain(&track.feat).collect();
+    if !artists.is_empty() {
+        tag.set_text_values("TPE1", artists);
+    }
+
+    if let Some(cddb) = track.cddb {
+        tag.add_frame(ExtendedText {
+            description: "CDDB".into(),
+            value: format!("{cddb:x}"),
+        });
+    }
+    if let Some(cdindex) = &track.cdindex {
+        tag.add_frame(ExtendedText {
+            description: "CDINDEX".into(),
+            value: cdindex.clone(),
+        });
+    }
+
+    if let Some(cdtoc) = &track.cdtoc {
+        tag.add_frame(ExtendedText {
+            description: "CDTOC".into(),
+            value: cdtoc.clone(),
+        });
+    }
+    if let Some(catalog) = &track.catalog {
+        tag.add_frame(ExtendedText {
+            description: "CATALOGNUMBER".into(),
+            value: catalog.clone(),
+        });
+    }
+    if let Some(barcode) = &track.barcode {
+        tag.add_frame(ExtendedText {
+            description: "BARCODE".into(),
+            value: barcode.clone(),
+        });
+    }
+    if let Some(lyrics) = &track.lyrics {
+        tag.add_frame(Lyrics {
+            lang: "eng".into(),
+            description: String::new(),
+            text: lyrics.text.clone(),
+        });
+    }
+    if let Some(comment) = &track.comment {
+        tag.add_frame(Comment {
+            lang: "eng".into(),
+            description: String::new(),
+            text: comment.clone(),
+        });
+    }
+
+    for (name, value) in tags::replay_gain(track) {
+        tag.add_frame(ExtendedText {
+            description: name.into(),
+            value,
+        });
+    }
+
+    if let Some(cover) = &track.cover {
+        tag.add_frame(Picture {
+            mime_type: cover.mime.to_owned(),
+            picture_type: PictureType::CoverFront,
+            description: String::new(),
+            data: cover.data.clone(),
+        });
+    }
+
+    tag
+}
+
+impl FromStr for Mp3Preset {
+    type Err = ArgError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let lower = s.to_ascii_lowercase();
+        match lower.as_str() {
+            "v0" => return Ok(Self::V0),
+            "v2" => return Ok(Self::V2),
+            "cbr" => return Ok(Self::Cbr(320)),
+            _ => {}
+        }
+
+        lower
+            .strip_prefix("cbr:")
+            .and_then(|b| b.parse().ok())
+            .filter(|b| CBR_BITRATES.contains(b))
+            .map(Self::Cbr)
+            .ok_or_else(|| {
+                ArgError::parse_msg("Invalid mp3 preset.", s.to_owned()).hint(
+                    "Valid presets are `v0`, `v2`, `cbr` and `cbr:<kbps>` \
+                    where kbps is one of 8, 16, 24, 32, 40, 48, 56, 64, 80, \
+                    96, 112, 128, 160, 192, 224, 256 or 320.",
+                )
+            })
+    }
+}
+
+impl FromArgStr for Mp3Preset {}
+
+impl Display for Mp3Preset {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::V0 => write!(f, "v0"),
+            Self::V2 => write!(f, "v2"),
+            Self::Cbr(b) => write!(f, "cbr:{b}"),
+        }
+    }
+}
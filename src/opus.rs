@@ -0,0 +1,56 @@
+use std::{path::Path, process::Command};
+
+use crate::{
+    config::EncoderSettings,
+    encoder::{self, Encoder},
+    err::Result,
+    tag_map::TagMap,
+    tags,
+    track_info::TrackInfo,
+};
+
+/// Encodes into ogg opus using the external program `opusenc`.
+pub struct Opus {
+    pub settings: EncoderSettings,
+    pub tags: TagMap,
+}
+
+impl Encoder for Opus {
+    fn extension(&self) -> &'static str {
+        "opus"
+    }
+
+    fn describe(&self) -> String {
+        let level = self
+            .settings
+            .level
+            .map_or_else(String::new, |l| format!(", level {l}"));
+        let args = encoder::describe_args(&self.settings.args);
+        format!("opus{level}{args}")
+    }
+
+    fn encode(
+        &self,
+        src: &Path,
+        dst: &Path,
+        track: &TrackInfo,
+        progress: &dyn Fn(u64),
+    ) -> Result<()> {
+        let mut cmd = Command::new("opusenc");
+        cmd.arg("--quiet");
+        if let Some(level) = self.settings.level {
+            cmd.args(["--comp", &level.to_string()]);
+        }
+        cmd.args(&self.settings.args);
+        for (name, value) in tags::opus_comments(track, &self.tags) {
+            cmd.args(["--comment", &format!("{name}={value}")]);
+        }
+        if let Some(cover) = &track.cover {
+            cmd.arg("--picture");
+            cmd.arg(&cover.path);
+        }
+        cmd.arg("-");
+        cmd.arg(dst);
+        encoder::run(&mut cmd, src, progress)
+    }
+}
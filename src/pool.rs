@@ -0,0 +1,45 @@
+use std::{
+    sync::{Mutex, mpsc},
+    thread,
+};
+
+/// Runs `job` for each of the `items` on at most `jobs` threads at once.
+/// `done` is called on the current thread with the result of each job as
+/// soon as the job finishes.
+pub fn run<I, J, R>(
+    jobs: usize,
+    items: I,
+    job: impl Fn(I::Item) -> R + Sync,
+    mut done: impl FnMut(R),
+) where
+    I: IntoIterator<IntoIter = J>,
+    J: Iterator<Item = I::Item> + Send,
+    I::Item: Send,
+    R: Send,
+{
+    let items = Mutex::new(items.into_iter());
+    let (tx, rx) = mpsc::channel();
+
+    thread::scope(|s| {
+        for _ in 0..jobs.max(1) {
+            let tx = tx.clone();
+            let items = &items;
+            let job = &job;
+            s.spawn(move || {
+                loop {
+                    let Some(item) = items.lock().unwrap().next() else {
+                        break;
+                    };
+                    if tx.send(job(item)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        for res in rx {
+            done(res);
+        }
+    });
+}
@@ -0,0 +1,242 @@
+use std::{
+    io::{self, IsTerminal, Write},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use termal::{codes, formatmc, raw::term_size};
+
+use crate::summary::Outcome;
+
+/// Minimum time between redraws caused by progress updates.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(100);
+const BAR_WIDTH: usize = 30;
+
+/// Shows progress of encoding. If stdout is terminal, shows live view of all
+/// the files. Otherwise prints line for each event.
+pub struct Progress {
+    inner: Mutex<Inner>,
+}
+
+struct Inner {
+    tasks: Vec<Task>,
+    start: Instant,
+    last_draw: Option<Instant>,
+    /// Number of lines drawn by the last redraw.
+    drawn: usize,
+    is_term: bool,
+}
+
+struct Task {
+    name: String,
+    /// Size of the source in bytes.
+    size: u64,
+    state: State,
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    Pending,
+    /// Number of bytes of the source that were processed.
+    Encoding(u64),
+    Verifying,
+    Done(Outcome),
+    Failed,
+}
+
+impl Progress {
+    /// Creates new progress with the given tasks given by their name and
+    /// the size of their source.
+    pub fn new<I>(tasks: I) -> Self
+    where
+        I: IntoIterator<Item = (String, u64)>,
+    {
+        let tasks = tasks
+            .into_iter()
+            .map(|(name, size)| Task {
+                name,
+                size,
+                state: State::Pending,
+            })
+            .collect();
+
+        let res = Self {
+            inner: Mutex::new(Inner {
+                tasks,
+                start: Instant::now(),
+                last_draw: None,
+                drawn: 0,
+                is_term: io::stdout().is_terminal(),
+            }),
+        };
+        res.inner.lock().unwrap().draw();
+        res
+    }
+
+    pub fn start(&self, id: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.tasks[id].state = State::Encoding(0);
+        if inner.is_term {
+            inner.draw();
+        } else {
+            println!("Encoding {}", inner.tasks[id].name);
+        }
+    }
+
+    /// Sets the number of processed bytes of the source.
+    pub fn update(&self, id: usize, bytes: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.tasks[id].state = State::Encoding(bytes);
+        if inner.is_term
+            && inner
+                .last_draw
+                .is_none_or(|d| d.elapsed() >= REDRAW_INTERVAL)
+        {
+            inner.draw();
+        }
+    }
+
+    /// Marks the task as being verified.
+    pub fn verify(&self, id: usize) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.tasks[id].state = State::Verifying;
+        if inner.is_term {
+            inner.draw();
+        } else {
+            println!("Verifying {}", inner.tasks[id].name);
+        }
+    }
+
+    /// Marks the task as finished. [`None`] means that the task failed.
+    pub fn finish(&self, id: usize, outcome: Option<Outcome>) {
+        let mut inner = self.inner.lock().unwrap();
+        let task = &mut inner.tasks[id];
+        task.state = outcome.map_or(State::Failed, State::Done);
+        if inner.is_term {
+            inner.draw();
+            return;
+        }
+
+        let name = &inner.tasks[id].name;
+        match outcome {
+            Some(Outcome::Encoded) => println!("Done {name}"),
+            Some(Outcome::Retagged) => println!("Retagged {name}"),
+            Some(Outcome::Skipped) => println!("Skipped {name}"),
+            None => println!("Failed {name}"),
+        }
+    }
+
+    /// Draws the final state and moves the cursor after it.
+    pub fn end(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.is_term {
+            inner.draw();
+            println!();
+        }
+    }
+}
+
+impl Inner {
+    fn draw(&mut self) {
+        if !self.is_term {
+            return;
+        }
+
+        let (width, height) = term_size()
+            .ok()
+            .filter(|s| s.char_width != 0 && s.char_height != 0)
+            .map_or((80, 24), |s| (s.char_width, s.char_height));
+        let mut out = String::new();
+        if self.drawn != 0 {
+            out += &codes::move_up!(self.drawn);
+        }
+        out += "\r";
+        out += codes::ERASE_TO_END;
+
+        // Leave space for the bar and the cursor. If the tasks don't fit,
+        // the first ones are not shown.
+        let skip = self.tasks.len().saturating_sub(height.saturating_sub(2));
+        let name_width = width.saturating_sub(18).clamp(10, 60);
+        for t in &self.tasks[skip..] {
+            let name: String = if t.name.chars().count() > name_width {
+                t.name
+                    .chars()
+                    .take(name_width - 3)
+                    .chain("...".chars())
+                    .collect()
+            } else {
+                t.name.clone()
+            };
+            let state = match t.state {
+                State::Pending => formatmc!(true, "{'gr}pending{'_}"),
+                State::Encoding(b) => {
+                    let p = percent(b, t.size);
+                    formatmc!(true, "{'y}encoding {p:>3}%{'_}")
+                }
+                State::Verifying => formatmc!(true, "{'y}verifying{'_}"),
+                State::Done(Outcome::Encoded) => {
+                    formatmc!(true, "{'g}done{'_}")
+                }
+                State::Done(Outcome::Retagged) => {
+                    formatmc!(true, "{'g}retagged{'_}")
+                }
+                State::Done(Outcome::Skipped) => {
+                    formatmc!(true, "{'gr}skipped{'_}")
+                }
+                State::Failed => formatmc!(true, "{'r}failed{'_}"),
+            };
+            out += &format!("  {name:<name_width$}  {state}\n");
+        }
+        out += &self.bar();
+
+        print!("{out}");
+        _ = io::stdout().flush();
+        self.drawn = self.tasks.len() - skip;
+        self.last_draw = Some(Instant::now());
+    }
+
+    fn bar(&self) -> String {
+        let total: u64 = self.tasks.iter().map(|t| t.size).sum();
+        let done: u64 = self
+            .tasks
+            .iter()
+            .map(|t| match t.state {
+                State::Pending => 0,
+                State::Encoding(b) => b.min(t.size),
+                State::Verifying | State::Done(_) | State::Failed => t.size,
+            })
+            .sum();
+
+        let ratio = if total == 0 {
+            1.
+        } else {
+            done as f64 / total as f64
+        };
+        let filled = (ratio * BAR_WIDTH as f64) as usize;
+        let full = "#".repeat(filled);
+        let empty = "-".repeat(BAR_WIDTH - filled);
+        let p = percent(done, total);
+
+        let elapsed = self.start.elapsed();
+        let eta = if ratio > 0. {
+            duration_str(elapsed.mul_f64((1. - ratio) / ratio))
+        } else {
+            "--:--".to_owned()
+        };
+        let elapsed = duration_str(elapsed);
+
+        formatmc!(
+            true,
+            "[{'g}{full}{'_}{empty}] {p:>3}%  elapsed {elapsed}  ETA {eta}"
+        )
+    }
+}
+
+fn percent(done: u64, total: u64) -> u64 {
+    (done * 100).checked_div(total).map_or(100, |p| p.min(100))
+}
+
+fn duration_str(d: Duration) -> String {
+    let s = d.as_secs();
+    format!("{:02}:{:02}", s / 60, s % 60)
+}
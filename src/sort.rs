@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+
+/// Leading articles that are moved to the end of the sort name.
+const ARTICLES: &[&str] = &["The", "A", "An"];
+
+/// Creates the name used for sorting from the given artist name.
+///
+/// Names in `overrides` are replaced with the given sort name. If the sort
+/// name is empty, the artist is person and the last name is moved to the
+/// front (`John Lennon` -> `Lennon, John`). Otherwise leading article is
+/// moved to the end (`The Beatles` -> `Beatles, The`).
+pub fn sort_name(name: &str, overrides: &HashMap<String, String>) -> String {
+    let name = name.trim();
+    match overrides.get(name).map(|s| s.trim()) {
+        Some("") => person_sort_name(name),
+        Some(sort) => sort.to_owned(),
+        None => article_sort_name(name),
+    }
+}
+
+fn person_sort_name(name: &str) -> String {
+    match name.rsplit_once(' ') {
+        Some((first, last)) => format!("{last}, {}", first.trim_end()),
+        None => name.to_owned(),
+    }
+}
+
+fn article_sort_name(name: &str) -> String {
+    let Some((article, rest)) = name.split_once(' ') else {
+        return name.to_owned();
+    };
+    let rest = rest.trim_start();
+    if rest.is_empty()
+        || !ARTICLES.iter().any(|a| a.eq_ignore_ascii_case(article))
+    {
+        return name.to_owned();
+    }
+    format!("{rest}, {article}")
+}
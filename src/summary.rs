@@ -0,0 +1,90 @@
+use std::{
+    io::{self, IsTerminal},
+    path::PathBuf,
+};
+
+use termal::{formatmc, printmcln};
+
+use crate::err::Result;
+
+/// Result of encoding single track.
+pub struct TrackResult {
+    /// Track number (or position in the album if the number is unknown).
+    pub track: usize,
+    pub path: PathBuf,
+    pub res: Result<Outcome>,
+    /// Whether the output decodes to the same audio as the source. [`None`]
+    /// if it wasn't verified.
+    pub verified: Option<bool>,
+}
+
+/// What was done with successfully processed track.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Encoded,
+    /// Only the metadata of existing file were updated.
+    Retagged,
+    /// Existing file was already up to date.
+    Skipped,
+}
+
+/// Results of all the tracks encoded in a single run.
+#[derive(Default)]
+pub struct Summary {
+    /// Output directories with the description of their encoder settings.
+    pub settings: Vec<(PathBuf, String)>,
+    pub tracks: Vec<TrackResult>,
+}
+
+impl Summary {
+    pub fn push(&mut self, res: TrackResult) {
+        self.tracks.push(res);
+    }
+
+    pub fn failed(&self) -> usize {
+        self.tracks.iter().filter(|t| t.res.is_err()).count()
+    }
+
+    pub fn print(&mut self) {
+        self.tracks
+            .sort_by(|a, b| a.track.cmp(&b.track).then(a.path.cmp(&b.path)));
+        let is_term = io::stdout().is_terminal();
+
+        if !self.settings.is_empty() {
+            printmcln!(is_term, "{'g}Settings:{'_}");
+        }
+        for (dir, settings) in &self.settings {
+            let dir = dir.to_string_lossy();
+            printmcln!(is_term, "  {'w}{dir}{'_}: {settings}");
+        }
+
+        printmcln!(is_term, "{'g}Summary:{'_}");
+        for t in &self.tracks {
+            let track = t.track;
+            let path = t.path.to_string_lossy();
+            let verified = match t.verified {
+                Some(true) => formatmc!(is_term, "{'g}verified{'_}  "),
+                Some(false) => formatmc!(is_term, "{'r}mismatch{'_}  "),
+                None => " ".repeat(10),
+            };
+            match &t.res {
+                Ok(Outcome::Encoded) => printmcln!(
+                    is_term,
+                    "  {track:>2}  {'g}ok{'_}        {verified}{path}"
+                ),
+                Ok(Outcome::Retagged) => printmcln!(
+                    is_term,
+                    "  {track:>2}  {'g}retagged{'_}  {verified}{path}"
+                ),
+                Ok(Outcome::Skipped) => printmcln!(
+                    is_term,
+                    "  {track:>2}  {'gr}skipped{'_}   {verified}{path}"
+                ),
+                Err(e) => printmcln!(
+                    is_term,
+                    "  {track:>2}  {'r}failed{'_}    {verified}{path}: {e}"
+                ),
+            }
+        }
+    }
+}
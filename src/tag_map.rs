@@ -0,0 +1,336 @@
+use std::fmt::Display;
+
+use log::warn;
+
+use crate::{
+    err::{Error, Result},
+    lyrics::Lyrics,
+    tags::parse,
+    track_info::TrackInfo,
+};
+
+/// Field of [`TrackInfo`] that is written as vorbis comment.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Title,
+    Artist,
+    Album,
+    Date,
+    Track,
+    TrackTotal,
+    Genre,
+    Isrc,
+    Feat,
+    Disc,
+    DiscTotal,
+    DiscName,
+    AlbumArtist,
+    ArtistSort,
+    AlbumArtistSort,
+    Compilation,
+    Cdindex,
+    Cddb,
+    Cdtoc,
+    Composer,
+    Lyricist,
+    Arranger,
+    Label,
+    Catalog,
+    Barcode,
+    Comment,
+    Lyrics,
+}
+
+/// Fields in the order in which they are written with their name in the
+/// config and their default tag name.
+const FIELDS: &[(Field, &str, &str)] = &[
+    (Field::Title, "title", "TITLE"),
+    (Field::Artist, "artist", "ARTIST"),
+    (Field::Album, "album", "ALBUM"),
+    (Field::Date, "date", "DATE"),
+    (Field::Track, "track", "TRACKNUMBER"),
+    (Field::TrackTotal, "track-total", "TRACKTOTAL"),
+    (Field::Genre, "genre", "GENRE"),
+    (Field::Isrc, "isrc", "ISRC"),
+    (Field::Feat, "feat", "ARTIST"),
+    (Field::Disc, "disc", "DISCNUMBER"),
+    (Field::DiscTotal, "disc-total", "DISCTOTAL"),
+    (Field::DiscName, "disc-name", "VOLUME"),
+    (Field::AlbumArtist, "album-artist", "ALBUMARTIST"),
+    (Field::ArtistSort, "artist-sort", "ARTISTSORT"),
+    (
+        Field::AlbumArtistSort,
+        "album-artist-sort",
+        "ALBUMARTISTSORT",
+    ),
+    (Field::Compilation, "compilation", "COMPILATION"),
+    (Field::Cdindex, "cdindex", "CDINDEX"),
+    (Field::Cddb, "cddb", "CDDB"),
+    (Field::Cdtoc, "cdtoc", "CDTOC"),
+    (Field::Composer, "composer", "COMPOSER"),
+    (Field::Lyricist, "lyricist", "LYRICIST"),
+    (Field::Arranger, "arranger", "ARRANGER"),
+    (Field::Label, "label", "LABEL"),
+    (Field::Catalog, "catalog", "CATALOGNUMBER"),
+    (Field::Barcode, "barcode", "BARCODE"),
+    (Field::Comment, "comment", "COMMENT"),
+    (Field::Lyrics, "lyrics", "LYRICS"),
+];
+
+/// Decides which vorbis comments are written for the fields of
+/// [`TrackInfo`].
+#[derive(Clone, Debug)]
+pub struct TagMap {
+    fields: Vec<FieldMap>,
+}
+
+#[derive(Clone, Debug)]
+struct FieldMap {
+    field: Field,
+    /// Name of the field in the config.
+    key: &'static str,
+    /// Names of the tags. The field is not written if this is empty.
+    names: Vec<String>,
+    /// Separator used to join multiple values into single tag. If this is
+    /// not set, each value is in separate tag.
+    join: Option<String>,
+}
+
+impl Default for TagMap {
+    fn default() -> Self {
+        Self {
+            fields: FIELDS
+                .iter()
+                .map(|(field, key, name)| FieldMap {
+                    field: *field,
+                    key,
+                    names: vec![name.to_string()],
+                    join: None,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TagMap {
+    /// Sets the setting `key` from the section `tags` of the config. Value
+    /// of `<field>` is list of tag names and value of `<field>.join` is the
+    /// separator of multiple values, optionally in quotes.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let key = key.trim();
+        let (name, join) = match key.strip_suffix(".join") {
+            Some(name) => (name, true),
+            None => (key, false),
+        };
+        let Some(field) = self.fields.iter_mut().find(|f| f.key == name)
+        else {
+            return Err(Error::InvalidConfig(format!(
+                "Unknown field '{name}' in tags."
+            )));
+        };
+
+        if join {
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            if value.is_empty() {
+                return Err(Error::InvalidConfig(format!(
+                    "Separator for '{name}' must not be empty."
+                )));
+            }
+            field.join = Some(value.to_owned());
+        } else {
+            field.names = value
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|n| !n.is_empty())
+                .map(|n| n.to_ascii_uppercase())
+                .collect();
+        }
+        Ok(())
+    }
+
+    /// Gets the vorbis comments for the fields of the track.
+    pub fn comments(&self, track: &TrackInfo) -> Vec<(&str, String)> {
+        let mut res: Vec<(&str, String)> = vec![];
+        for f in &self.fields {
+            let values = f.field.get(track);
+            if values.is_empty() {
+                continue;
+            }
+
+            for name in &f.names {
+                let Some(sep) = &f.join else {
+                    res.extend(
+                        values.iter().map(|v| (name.as_str(), v.clone())),
+                    );
+                    continue;
+                };
+
+                // Joined values are appended to the previous field with the
+                // same name.
+                let value = values.join(sep);
+                match res.iter_mut().find(|(n, _)| n == name) {
+                    Some((_, v)) => *v = format!("{v}{sep}{value}"),
+                    None => res.push((name, value)),
+                }
+            }
+        }
+        res
+    }
+
+    /// Reads the fields from vorbis comments. This is inverse to
+    /// [`TagMap::comments`]. Tags with the default names are also read so
+    /// that the fields are not lost if the mapping changes.
+    pub fn read(&self, comments: &[(String, String)]) -> TrackInfo {
+        let default = Self::default();
+        let mut res = TrackInfo::default();
+        for (name, value) in comments {
+            let name = name.to_ascii_uppercase();
+            let mut fields = self.fields_with_name(&name);
+            if fields.is_empty() {
+                fields = default.fields_with_name(&name);
+            }
+
+            let parts: Vec<_> =
+                match fields.iter().find_map(|f| f.join.as_ref()) {
+                    Some(sep) => value.split(sep.as_str()).collect(),
+                    None => vec![value.as_str()],
+                };
+            for part in parts {
+                // Extra values of fields with single value are ignored.
+                fields.iter().any(|f| f.field.set(&mut res, &name, part));
+            }
+        }
+        res
+    }
+
+    /// Checks whether the tag with the given name is produced by cdadd.
+    pub fn is_known(&self, name: &str) -> bool {
+        self.fields
+            .iter()
+            .any(|f| f.names.iter().any(|n| n.eq_ignore_ascii_case(name)))
+            || FIELDS.iter().any(|(_, _, n)| n.eq_ignore_ascii_case(name))
+    }
+
+    fn fields_with_name(&self, name: &str) -> Vec<&FieldMap> {
+        self.fields
+            .iter()
+            .filter(|f| f.names.iter().any(|n| n == name))
+            .collect()
+    }
+}
+
+impl Field {
+    /// Gets the values of the field in the track.
+    fn get(self, track: &TrackInfo) -> Vec<String> {
+        fn one<T>(value: Option<T>) -> Vec<String>
+        where
+            T: Display,
+        {
+            value.iter().map(|v| v.to_string()).collect()
+        }
+
+        match self {
+            Self::Title => one(track.title.as_ref()),
+            Self::Artist => one(track.artist.as_ref()),
+            Self::Album => one(track.album.as_ref()),
+            Self::Date => one(track.date),
+            Self::Track => one(track.track),
+            Self::TrackTotal => one(track.track_total),
+            Self::Genre => one(track.genre.as_ref()),
+            Self::Isrc => one(track.isrc.as_ref()),
+            Self::Feat => track.feat.clone(),
+            Self::Disc => one(track.disc),
+            Self::DiscTotal => one(track.disc_total),
+            Self::DiscName => one(track.disc_name.as_ref()),
+            Self::AlbumArtist => one(track.album_artist.as_ref()),
+            Self::ArtistSort => one(track.artist_sort.as_ref()),
+            Self::AlbumArtistSort => one(track.album_artist_sort.as_ref()),
+            Self::Compilation => one(track.compilation.then_some(1)),
+            Self::Cdindex => one(track.cdindex.as_ref()),
+            Self::Cddb => one(track.cddb.map(|c| format!("{c:x}"))),
+            Self::Cdtoc => one(track.cdtoc.as_ref()),
+            Self::Composer => one(track.composer.as_ref()),
+            Self::Lyricist => one(track.lyricist.as_ref()),
+            Self::Arranger => one(track.arranger.as_ref()),
+            Self::Label => one(track.label.as_ref()),
+            Self::Catalog => one(track.catalog.as_ref()),
+            Self::Barcode => one(track.barcode.as_ref()),
+            Self::Comment => one(track.comment.as_ref()),
+            Self::Lyrics => one(track
+                .lyrics
+                .as_ref()
+                .map(|l| &l.text)
+                .filter(|t| !t.is_empty())),
+        }
+    }
+
+    /// Sets the field in the track from value of tag with the given name.
+    /// Returns `false` if the field already has value and cannot have more
+    /// values.
+    fn set(self, track: &mut TrackInfo, name: &str, value: &str) -> bool {
+        fn set<T>(
+            field: &mut Option<T>,
+            value: impl FnOnce() -> Option<T>,
+        ) -> bool {
+            if field.is_some() {
+                return false;
+            }
+            *field = value();
+            true
+        }
+
+        let string = || Some(value.to_owned());
+        match self {
+            Self::Title => set(&mut track.title, string),
+            Self::Artist => set(&mut track.artist, string),
+            Self::Album => set(&mut track.album, string),
+            Self::Date => set(&mut track.date, || parse(name, value)),
+            Self::Track => set(&mut track.track, || parse(name, value)),
+            Self::TrackTotal => {
+                set(&mut track.track_total, || parse(name, value))
+            }
+            Self::Genre => set(&mut track.genre, string),
+            Self::Isrc => set(&mut track.isrc, string),
+            Self::Feat => {
+                track.feat.push(value.to_owned());
+                true
+            }
+            Self::Disc => set(&mut track.disc, || parse(name, value)),
+            Self::DiscTotal => {
+                set(&mut track.disc_total, || parse(name, value))
+            }
+            Self::DiscName => set(&mut track.disc_name, string),
+            Self::AlbumArtist => set(&mut track.album_artist, string),
+            Self::ArtistSort => set(&mut track.artist_sort, string),
+            Self::AlbumArtistSort => set(&mut track.album_artist_sort, string),
+            Self::Compilation => {
+                track.compilation = value.trim() == "1";
+                true
+            }
+            Self::Cdindex => set(&mut track.cdindex, string),
+            Self::Cddb => set(&mut track.cddb, || {
+                u32::from_str_radix(value, 16)
+                    .inspect_err(|e| {
+                        warn!("Invalid value of {name} '{value}': {e}")
+                    })
+                    .ok()
+            }),
+            Self::Cdtoc => set(&mut track.cdtoc, string),
+            Self::Composer => set(&mut track.composer, string),
+            Self::Lyricist => set(&mut track.lyricist, string),
+            Self::Arranger => set(&mut track.arranger, string),
+            Self::Label => set(&mut track.label, string),
+            Self::Catalog => set(&mut track.catalog, string),
+            Self::Barcode => set(&mut track.barcode, string),
+            Self::Comment => set(&mut track.comment, string),
+            Self::Lyrics => set(&mut track.lyrics, || {
+                Some(Lyrics {
+                    text: value.to_owned(),
+                    lrc: None,
+                })
+            }),
+        }
+    }
+}
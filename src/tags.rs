@@ -0,0 +1,107 @@
+use std::{fmt::Display, str::FromStr};
+
+use log::warn;
+
+use crate::{tag_map::TagMap, track_info::TrackInfo};
+
+/// Names of the ReplayGain vorbis comments produced by [`vorbis_comments`].
+const REPLAY_GAIN_NAMES: &[&str] = &[
+    "REPLAYGAIN_TRACK_GAIN",
+    "REPLAYGAIN_TRACK_PEAK",
+    "REPLAYGAIN_ALBUM_GAIN",
+    "REPLAYGAIN_ALBUM_PEAK",
+];
+
+/// Gets the vorbis comments that describe the given track. Names of the
+/// comments are given by `map`.
+pub fn vorbis_comments<'a>(
+    track: &TrackInfo,
+    map: &'a TagMap,
+) -> Vec<(&'a str, String)> {
+    let mut res = map.comments(track);
+    res.extend(replay_gain(track));
+    res
+}
+
+/// Gets the comments for opus. Opus uses R128 gain relative to -23 LUFS in
+/// Q7.8 instead of ReplayGain.
+pub fn opus_comments<'a>(
+    track: &TrackInfo,
+    map: &'a TagMap,
+) -> Vec<(&'a str, String)> {
+    let mut res = map.comments(track);
+
+    // ReplayGain 2.0 reference is -18 LUFS.
+    let r128 = |gain: f64| (((gain - 5.) * 256.).round() as i16).to_string();
+    if let Some(gain) = track.track_gain {
+        res.push(("R128_TRACK_GAIN", r128(gain)));
+    }
+    if let Some(gain) = track.album_gain {
+        res.push(("R128_ALBUM_GAIN", r128(gain)));
+    }
+    res
+}
+
+/// Gets the ReplayGain 2.0 tags.
+pub fn replay_gain(track: &TrackInfo) -> Vec<(&'static str, String)> {
+    let gain = |g: Option<f64>| g.map(|g| format!("{g:.2} dB"));
+    let peak = |p: Option<f64>| p.map(|p| format!("{p:.6}"));
+    [
+        ("REPLAYGAIN_TRACK_GAIN", gain(track.track_gain)),
+        ("REPLAYGAIN_TRACK_PEAK", peak(track.track_peak)),
+        ("REPLAYGAIN_ALBUM_GAIN", gain(track.album_gain)),
+        ("REPLAYGAIN_ALBUM_PEAK", peak(track.album_peak)),
+    ]
+    .into_iter()
+    .flat_map(|(n, v)| v.map(|v| (n, v)))
+    .collect()
+}
+
+/// Reads the track info from vorbis comments. This is inverse to
+/// [`vorbis_comments`].
+pub fn from_vorbis_comments(
+    comments: &[(String, String)],
+    map: &TagMap,
+) -> TrackInfo {
+    let mut res = map.read(comments);
+    for (name, value) in comments {
+        match name.to_ascii_uppercase().as_str() {
+            "REPLAYGAIN_TRACK_GAIN" => res.track_gain = parse_gain(value),
+            "REPLAYGAIN_TRACK_PEAK" => res.track_peak = parse(name, value),
+            "REPLAYGAIN_ALBUM_GAIN" => res.album_gain = parse_gain(value),
+            "REPLAYGAIN_ALBUM_PEAK" => res.album_peak = parse(name, value),
+            _ => {}
+        }
+    }
+
+    res
+}
+
+/// Parses the value of tag with the given name. Invalid value is logged and
+/// ignored.
+pub fn parse<T>(name: &str, value: &str) -> Option<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    value
+        .parse()
+        .inspect_err(|e| warn!("Invalid value of {name} '{value}': {e}"))
+        .ok()
+}
+
+fn parse_gain(value: &str) -> Option<f64> {
+    let gain = value.trim_end_matches("dB").trim();
+    gain.parse()
+        .inspect_err(|e| warn!("Invalid replay gain '{value}': {e}"))
+        .ok()
+}
+
+/// Checks whether the vorbis comment with the given name is produced by
+/// [`vorbis_comments`].
+pub fn is_known(name: &str, map: &TagMap) -> bool {
+    map.is_known(name)
+        || REPLAY_GAIN_NAMES
+            .iter()
+            .any(|n| n.eq_ignore_ascii_case(name))
+}
@@ -1,9 +1,11 @@
-use std::{any::type_name, fmt::Display, path::Path, str::FromStr};
+use std::{any::type_name, fmt::Display, path::Path, str::FromStr, sync::Arc};
 
 use ini::{Ini, ParseOption, Properties};
 use log::{error, warn};
 
-use crate::{date::Date, err::Result, get_perf::get_perf};
+use crate::{
+    cover::Cover, date::Date, err::Result, get_perf::get_perf, lyrics::Lyrics,
+};
 
 #[derive(Default, Debug)]
 pub struct TrackInfo {
@@ -11,18 +13,54 @@ pub struct TrackInfo {
     pub cdindex: Option<String>,
     pub cddb: Option<u32>,
     pub album_artist: Option<String>,
+    pub album_artist_sort: Option<String>,
     pub disc_name: Option<String>,
     pub album: Option<String>,
     pub disc: Option<usize>,
+    /// Number of discs in the set.
+    pub disc_total: Option<usize>,
     pub date: Option<Date>,
     pub genre: Option<String>,
+    pub cover: Option<Arc<Cover>>,
+    /// Barcode (UPC/EAN). It is stored on the disc as media catalog
+    /// number.
+    pub barcode: Option<String>,
+    pub label: Option<String>,
+    pub catalog: Option<String>,
+    /// Table of contents of the disc in the format of the `CDTOC` tag.
+    pub cdtoc: Option<String>,
+    /// Album with tracks by various artists.
+    pub compilation: bool,
 
     // track info
     pub isrc: Option<String>,
     pub artist: Option<String>,
+    pub artist_sort: Option<String>,
     pub feat: Vec<String>,
     pub title: Option<String>,
+    pub composer: Option<String>,
+    pub lyricist: Option<String>,
+    pub arranger: Option<String>,
+    pub comment: Option<String>,
+    pub lyrics: Option<Lyrics>,
     pub track: Option<usize>,
+    pub track_total: Option<usize>,
+
+    // position on the disc
+    /// First sector of the track.
+    pub start: Option<u32>,
+    /// Length of the track in sectors.
+    pub length: Option<u32>,
+    /// Numbers of the indices after index 1 with their offsets in sectors
+    /// from the track start.
+    pub indices: Vec<(u32, u32)>,
+    pub pre_emphasis: bool,
+
+    // ReplayGain 2.0
+    pub track_gain: Option<f64>,
+    pub track_peak: Option<f64>,
+    pub album_gain: Option<f64>,
+    pub album_peak: Option<f64>,
 }
 
 impl TrackInfo {
@@ -58,23 +96,49 @@ impl TrackInfo {
             cdindex: Self::get_string(inf, "CDINDEX_DISCID"),
             cddb: Self::get_hex_u32(inf, "CDDB_DISCID"),
             album_artist: Self::get_string(inf, "Albumperformer"),
+            album_artist_sort: None,
             disc_name: Self::get_string(inf, "Albumtitle"),
             album: None,
             disc: None,
+            disc_total: None,
             date: None,
             genre: None,
+            cover: None,
+            barcode: Self::get_string(inf, "MCN"),
+            label: None,
+            catalog: None,
+            cdtoc: None,
+            compilation: false,
 
             isrc: Self::get_string(inf, "ISRC"),
             artist: Self::get_artist(inf, "Performer"),
+            artist_sort: None,
             feat,
             title,
+            composer: Self::get_optional_string(inf, "Composer"),
+            lyricist: Self::get_optional_string(inf, "Songwriter"),
+            arranger: Self::get_optional_string(inf, "Arranger"),
+            comment: Self::get_optional_string(inf, "Message"),
+            lyrics: None,
             track: Self::get_parse(inf, "Track"),
+            track_total: None,
+
+            start: Self::get_parse(inf, "Trackstart"),
+            length: Self::get_length(inf),
+            indices: Self::get_indices(inf),
+            pre_emphasis: inf.get("Pre-emphasis") == Some("yes"),
+
+            track_gain: None,
+            track_peak: None,
+            album_gain: None,
+            album_peak: None,
         })
     }
 
     pub fn normalize(&mut self) {
         if self.album_artist == self.artist {
             self.album_artist = None;
+            self.album_artist_sort = None;
         }
         if self.disc_name == self.album {
             self.disc_name = None;
@@ -95,6 +159,34 @@ impl TrackInfo {
         }
     }
 
+    /// Gets the track length in sectors. The length is followed by the
+    /// number of remaining samples.
+    fn get_length(inf: &Properties) -> Option<u32> {
+        let len = inf.get("Tracklength")?;
+        let sectors = len.split(',').next().unwrap_or_default().trim();
+        sectors
+            .parse()
+            .inspect_err(|e| {
+                error!("Failed to parse track length '{len}': {e}")
+            })
+            .ok()
+    }
+
+    /// Gets the indices after index 1. The offsets are listed from index 1
+    /// and unknown indices are `-1`.
+    fn get_indices(inf: &Properties) -> Vec<(u32, u32)> {
+        let Some(idx) = inf.get("Index") else {
+            return vec![];
+        };
+        idx.split_whitespace()
+            .zip(1..)
+            .skip(1)
+            .filter_map(|(i, n)| Some((n, i.parse::<i64>().ok()?)))
+            .filter(|(_, i)| *i > 0)
+            .map(|(n, i)| (n, i as u32))
+            .collect()
+    }
+
     fn get_string<S>(inf: &mut Properties, name: S) -> Option<String>
     where
         S: AsRef<str>,
@@ -118,6 +210,17 @@ impl TrackInfo {
         }
     }
 
+    /// Gets string that is usually not present, so it is not reported if it
+    /// is missing or empty.
+    fn get_optional_string(
+        inf: &mut Properties,
+        name: &str,
+    ) -> Option<String> {
+        let s = inf.remove(name)?;
+        let s = s.trim_matches('\'');
+        (!s.is_empty()).then(|| s.to_string())
+    }
+
     fn get_parse<V, S>(inf: &Properties, name: S) -> Option<V>
     where
         S: AsRef<str>,
@@ -174,3 +277,21 @@ impl TrackInfo {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn indices() {
+        let indices = |value: &str| {
+            let inf =
+                Ini::load_from_str(&format!("Index=\t\t{value}\n")).unwrap();
+            TrackInfo::get_indices(inf.general_section())
+        };
+        assert_eq!(indices("0"), []);
+        assert_eq!(indices("0 100"), [(2, 100)]);
+        // Missing index doesn't change the numbers of the next indices.
+        assert_eq!(indices("0 -1 500"), [(3, 500)]);
+    }
+}
@@ -0,0 +1,243 @@
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Take, Write},
+    path::Path,
+};
+
+use crate::err::{Error, Result};
+
+const FORMAT_PCM: u16 = 1;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+
+/// Format of PCM audio data.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PcmFormat {
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits: u16,
+}
+
+impl PcmFormat {
+    /// Format of audio CD.
+    pub const CD: Self = Self {
+        channels: 2,
+        sample_rate: 44100,
+        bits: 16,
+    };
+
+    /// Number of bytes used by single sample of single channel.
+    pub fn sample_bytes(&self) -> usize {
+        self.bits.div_ceil(8) as usize
+    }
+
+    /// Number of bytes used by single sample of all the channels.
+    pub fn block_align(&self) -> usize {
+        self.sample_bytes() * self.channels as usize
+    }
+}
+
+/// Reads PCM samples from wav file.
+pub struct WavReader {
+    pub format: PcmFormat,
+    /// Number of samples per channel.
+    pub len: u64,
+    data: Take<BufReader<File>>,
+    buf: Vec<u8>,
+}
+
+impl WavReader {
+    pub fn open<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut file = BufReader::new(File::open(path)?);
+        let file_len = file.get_ref().metadata()?.len();
+
+        let mut head = [0; 12];
+        file.read_exact(&mut head)?;
+        if &head[..4] != b"RIFF" || &head[8..] != b"WAVE" {
+            return Err(Error::InvalidWav("Missing RIFF/WAVE header."));
+        }
+
+        let mut format = None;
+        loop {
+            let mut chunk = [0; 8];
+            file.read_exact(&mut chunk)?;
+            let size = u32::from_le_bytes(chunk[4..].try_into().unwrap());
+            match &chunk[..4] {
+                b"fmt " => {
+                    let mut fmt = vec![0; size as usize + (size & 1) as usize];
+                    file.read_exact(&mut fmt)?;
+                    format = Some(Self::parse_format(&fmt)?);
+                }
+                b"data" => {
+                    let Some(format) = format else {
+                        return Err(Error::InvalidWav(
+                            "Data chunk before format chunk.",
+                        ));
+                    };
+                    // Some programs don't fill the size when writing to
+                    // stream, so the rest of the file is used.
+                    let pos = file.stream_position()?;
+                    let rest = file_len.saturating_sub(pos);
+                    let size = if size == 0 || size as u64 > rest {
+                        rest
+                    } else {
+                        size as u64
+                    };
+                    let align = format.block_align() as u64;
+                    return Ok(Self {
+                        format,
+                        len: size / align,
+                        data: file.take(size / align * align),
+                        buf: vec![],
+                    });
+                }
+                _ => {
+                    file.seek(SeekFrom::Current(
+                        size as i64 + (size & 1) as i64,
+                    ))?;
+                }
+            }
+        }
+    }
+
+    /// Reads at most `cnt` samples for each channel into `out`. Samples
+    /// are interleaved. Returns the number of samples per channel that were
+    /// read. Returns 0 at the end of the data.
+    pub fn read_samples(
+        &mut self,
+        out: &mut Vec<i32>,
+        cnt: usize,
+    ) -> Result<usize> {
+        let align = self.format.block_align();
+        self.buf.resize(cnt * align, 0);
+        let mut len = 0;
+        while len < self.buf.len() {
+            match self.data.read(&mut self.buf[len..])? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        let len = len / align;
+
+        out.clear();
+        let bytes = self.format.sample_bytes();
+        // Samples are aligned to the most significant bit of the container,
+        // so move them to the top of i32 and shift them back.
+        let shift = 32 - self.format.bits as u32;
+        for s in self.buf[..len * align].chunks_exact(bytes) {
+            let s = match bytes {
+                1 => (s[0] as i32 - 128) << 24,
+                2 => i32::from_le_bytes([0, 0, s[0], s[1]]),
+                3 => i32::from_le_bytes([0, s[0], s[1], s[2]]),
+                _ => i32::from_le_bytes([s[0], s[1], s[2], s[3]]),
+            };
+            out.push(s >> shift);
+        }
+
+        Ok(len)
+    }
+
+    fn parse_format(fmt: &[u8]) -> Result<PcmFormat> {
+        if fmt.len() < 16 {
+            return Err(Error::InvalidWav("Format chunk is too short."));
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([fmt[i], fmt[i + 1]]);
+
+        let mut tag = u16_at(0);
+        if tag == FORMAT_EXTENSIBLE && fmt.len() >= 26 {
+            // The first two bytes of the subformat GUID are the format tag.
+            tag = u16_at(24);
+        }
+        if tag != FORMAT_PCM {
+            return Err(Error::InvalidWav("Only PCM data is supported."));
+        }
+
+        let format = PcmFormat {
+            channels: u16_at(2),
+            sample_rate: u32::from_le_bytes(fmt[4..8].try_into().unwrap()),
+            bits: u16_at(14),
+        };
+
+        if format.channels == 0 || format.channels > 8 {
+            return Err(Error::InvalidWav("Unsupported number of channels."));
+        }
+        if format.bits == 0 || format.bits > 32 {
+            return Err(Error::InvalidWav("Unsupported bits per sample."));
+        }
+        if format.sample_rate == 0 {
+            return Err(Error::InvalidWav("Invalid sample rate."));
+        }
+
+        Ok(format)
+    }
+}
+
+/// Writes PCM samples into wav file.
+pub struct WavWriter {
+    format: PcmFormat,
+    file: BufWriter<File>,
+    /// Size of the written data in bytes.
+    len: u64,
+    buf: Vec<u8>,
+}
+
+impl WavWriter {
+    /// Creates the wav file. The sizes in the header are written by
+    /// [`WavWriter::finish`].
+    pub fn create<P>(path: P, format: PcmFormat) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let mut file = BufWriter::new(File::create(path)?);
+        let align = format.block_align() as u16;
+        file.write_all(b"RIFF\0\0\0\0WAVEfmt ")?;
+        file.write_all(&16_u32.to_le_bytes())?;
+        file.write_all(&FORMAT_PCM.to_le_bytes())?;
+        file.write_all(&format.channels.to_le_bytes())?;
+        file.write_all(&format.sample_rate.to_le_bytes())?;
+        file.write_all(&(format.sample_rate * align as u32).to_le_bytes())?;
+        file.write_all(&align.to_le_bytes())?;
+        file.write_all(&format.bits.to_le_bytes())?;
+        file.write_all(b"data\0\0\0\0")?;
+        Ok(Self {
+            format,
+            file,
+            len: 0,
+            buf: vec![],
+        })
+    }
+
+    /// Writes interleaved samples.
+    pub fn write_samples(&mut self, samples: &[i32]) -> Result<()> {
+        let bytes = self.format.sample_bytes();
+        let shift = bytes as u32 * 8 - self.format.bits as u32;
+        self.buf.clear();
+        for s in samples {
+            let s = s << shift;
+            match bytes {
+                1 => self.buf.push((s + 128) as u8),
+                _ => self.buf.extend_from_slice(&s.to_le_bytes()[..bytes]),
+            }
+        }
+        self.file.write_all(&self.buf)?;
+        self.len += self.buf.len() as u64;
+        Ok(())
+    }
+
+    /// Writes the sizes into the header and flushes the file.
+    pub fn finish(mut self) -> Result<()> {
+        if self.len & 1 == 1 {
+            self.file.write_all(&[0])?;
+        }
+        let size = self.len as u32;
+        self.file.seek(SeekFrom::Start(4))?;
+        self.file
+            .write_all(&(36 + size + (size & 1)).to_le_bytes())?;
+        self.file.seek(SeekFrom::Start(40))?;
+        self.file.write_all(&size.to_le_bytes())?;
+        self.file.flush()?;
+        Ok(())
+    }
+}